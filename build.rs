@@ -1,12 +1,35 @@
+/// Variables `wifi.rs` reads with `env!`, only needed by builds connecting to WiFi.
+const WIFI_VARS: [&str; 2] = ["SSID", "PASSWORD"];
+
 fn main() {
     // println!("cargo:rustc-link-arg-bins=-Tlinkall.x");
     //
     // println!("cargo:rustc-link-arg-bins=-Trom_functions.x");
 
     println!("cargo:rerun-if-changed=.env");
+    for var in WIFI_VARS {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
 
-    let dotenv = dotenvy::vars();
-    dotenv.for_each(|kv| {
+    let dotenv: Vec<(String, String)> = dotenvy::vars().collect();
+    dotenv.iter().for_each(|kv| {
         println!("cargo:rustc-env={}={}", kv.0, kv.1);
     });
+
+    // same condition as the `wifi` module in lib.rs
+    let wifi = std::env::var_os("CARGO_FEATURE_MCU").is_some()
+        && std::env::var_os("CARGO_FEATURE_OFFLINE").is_none();
+    if wifi {
+        let missing: Vec<&str> = WIFI_VARS
+            .into_iter()
+            .filter(|var| !dotenv.iter().any(|(k, v)| k == var && !v.is_empty()))
+            .collect();
+        if !missing.is_empty() {
+            panic!(
+                "missing WiFi credentials: {}. Copy .env.template to .env next to Cargo.toml \
+                 and fill them in, or build with the `offline` feature.",
+                missing.join(", ")
+            );
+        }
+    }
 }
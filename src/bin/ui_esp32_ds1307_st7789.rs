@@ -5,9 +5,8 @@
 extern crate alloc;
 
 use alloc::vec;
-use alloc::{boxed::Box, rc::Rc};
-use chrono::Timelike;
-use chrono_tz::Europe::Paris;
+use alloc::{boxed::Box, format, rc::Rc, string::String, vec::Vec};
+use chrono::Datelike;
 use debouncr::debounce_stateful_2;
 use embassy_executor::Spawner;
 use embassy_futures::select::select;
@@ -15,14 +14,28 @@ use embassy_futures::select::select;
 use embassy_net::tcp::client::{TcpClient, TcpClientState};
 use embassy_net::tcp::TcpSocket;
 use embassy_net::StackResources;
-use embassy_net::{Runner, Stack};
-use embassy_sync::mutex::Mutex;
-use embassy_time::{Duration, Instant, Ticker, Timer};
-use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565, prelude::RgbColor};
+use embassy_net::Runner;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_graphics::{
+    draw_target::DrawTarget,
+    mono_font::{ascii::FONT_9X15, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::{Dimensions, Drawable, Point, RgbColor, Size},
+    primitives::Rectangle,
+    text::Text,
+};
 use embedded_hal_bus::spi::ExclusiveDevice;
 
+use esp32_mipidsi_clock::buttons::{self, Button, ButtonCommand, ButtonMap, Press};
 use esp32_mipidsi_clock::controller::WallClock;
-use esp32_mipidsi_clock::ntp::{await_now, now, NtpClient};
+use esp32_mipidsi_clock::cpu_clock;
+use esp32_mipidsi_clock::singleton;
+use esp32_mipidsi_clock::tasks::{self, ClockTasks};
+#[cfg(not(feature = "offline"))]
+use esp32_mipidsi_clock::ntp::NtpClient;
+#[cfg(feature = "rtc-32khz")]
+use esp32_mipidsi_clock::timebase::Ds3231Timebase;
+#[cfg(not(feature = "offline"))]
 use esp32_mipidsi_clock::wifi::EspEmbassyWifiController;
 use esp_hal::gpio::{Flex, Input};
 use esp_hal::{
@@ -40,6 +53,7 @@ use esp_hal::{
     time::RateExtU32,
     timer::timg::TimerGroup,
     tsens::TemperatureSensor,
+    usb_serial_jtag::UsbSerialJtag,
 };
 use esp_hal::{
     dma_buffers,
@@ -55,29 +69,29 @@ use esp_backtrace as _;
 
 use ds323x::{DateTimeAccess, Ds323x, NaiveDate};
 use esp32_mipidsi_clock::{
-    board::{types::LedChannel, Board},
+    board::Board,
     boards::DrawBuffer,
     controller::Controller,
-    slintplatform::EspEmbassyBackend,
+    slintplatform::{AnimationTracker, EspEmbassyBackend},
 };
 use esp32_mipidsi_clock::{
     board::{
         types::{DisplayImpl, RTCUtils},
-        RtcRelated,
+        RtcRelated, TimeSource,
     },
     controller::{self, Action},
+    settings::{self, FlashSettingsStore},
 };
 use esp_wifi::{
     wifi::{WifiDevice, WifiStaDevice},
     EspWifiController,
 };
-use log::{info, log};
 // use meteofrance_rs::client_no_std::{HttpGetClient, HttpGetResponse};
 use mipidsi::{
     interface::SpiInterface,
     models::GC9A01,
     // models::ST7789,
-    options::{ColorInversion, TearingEffect},
+    options::{ColorInversion, Orientation, Rotation, TearingEffect},
     Builder,
 };
 
@@ -98,29 +112,67 @@ use slint_generated::{Globals, Recipe, TimeOfDay};
 //     client::ClientNoQueue;
 // };
 
-macro_rules! singleton {
-    ($val:expr, $T:ty) => {{
-        static STATIC_CELL: ::static_cell::StaticCell<$T> = ::static_cell::StaticCell::new();
-        STATIC_CELL.init($val)
-    }};
-}
-
-pub const DISPLAY_WIDTH: usize = 240;
-pub const DISPLAY_HEIGHT: usize = 240;
-
 const SLINT_TARGET_FPS: u64 = 25;
 const SLINT_FRAME_DURATION_MS: u64 = 1000 / SLINT_TARGET_FPS;
 
+/// Pixels the frames are rendered in: `slint::Rgb8Pixel` dithers them down to the panel's 16
+/// bits colors, smoother sky gradients at a small CPU cost and a line buffer half as large
+/// again. `software_renderer::Rgb565Pixel` renders straight in the panel's colors, without
+/// dithering.
+type FramePixel = slint::Rgb8Pixel;
+
+/// GPIOs of the rotary encoder A and B channels, see `poll_encoder`.
+#[cfg(feature = "encoder")]
+const ENCODER_A_GPIO: u8 = 20;
+#[cfg(feature = "encoder")]
+const ENCODER_B_GPIO: u8 = 21;
+/// Time for the encoder contacts to settle after an edge.
+#[cfg(feature = "encoder")]
+const ENCODER_DEBOUNCE: Duration = Duration::from_millis(2);
+
+/// Button scan interval while nothing is pressed, still instant for a human.
+const BUTTON_IDLE_POLL: Duration = Duration::from_millis(20);
+/// Button scan interval once a press starts, until it is debounced.
+const BUTTON_DEBOUNCE_POLL: Duration = Duration::from_millis(1);
+
+/// How long the results of the boot self test stay on screen.
+const SELF_TEST_RESULTS_DISPLAY: Duration = Duration::from_secs(10);
+/// Chip temperatures, in celsius, the self test accepts.
+const SELF_TEST_TEMPERATURE_RANGE: core::ops::RangeInclusive<f32> = -10.0..=85.0;
+
+/// Number of frames averaged by the frame stats overlay.
+const FRAME_PROFILER_WINDOW: usize = 16;
+
+/// A panel refreshes at ~60Hz, so a TE edge never takes longer than a few frames.
+const TEARING_EFFECT_TIMEOUT_MS: u64 = 50;
+
+/// Color the panel is cleared with at boot, until the first frame. Black keeps a bedside
+/// clock from flashing in a dark room, `Rgb565::new(0, 0, 2)` is the night sky and
+/// `Rgb565::WHITE` shows a dead panel right away.
+const BOOT_CLEAR_COLOR: Rgb565 = Rgb565::BLACK;
+
+/// Falls back on the internal RTC when the DS3231 is missing, set to `TimeSource::INTERNAL`
+/// to ignore the module.
+const TIME_SOURCE: TimeSource = TimeSource::DS3231;
+
+/// 80MHz draws less power and keeps up with the clock face, the animations and the fades are
+/// smoother at 160MHz.
+const CPU_CLOCK: CpuClock = CpuClock::_160MHz;
+/// Opt-in clock while nothing animates or the screen is off, back to `CPU_CLOCK` for the
+/// animations, see `cpu_clock`. Only below a 160MHz `CPU_CLOCK`. `None` stays at `CPU_CLOCK`.
+const CPU_CLOCK_IDLE: Option<CpuClock> = None;
+
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
     esp_alloc::heap_allocator!(130 * 1024);
     esp_println::logger::init_logger_from_env();
 
     let mut config = esp_hal::Config::default();
-    config.cpu_clock = CpuClock::_160MHz;
+    config.cpu_clock = CPU_CLOCK;
     let peripherals = esp_hal::init(config);
+    cpu_clock::init(CPU_CLOCK);
 
-    // log::info!("running at {}", peripherals.);
+    log::info!("running at {}MHz", cpu_clock::cpu_mhz());
 
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_hal_embassy::init(timg0.timer0);
@@ -139,7 +191,7 @@ async fn main(spawner: Spawner) {
     );
     lstimer0
         .configure(timer::config::Config {
-            duty: timer::config::Duty::Duty5Bit,
+            duty: tasks::BACKLIGHT_DUTY_RESOLUTION,
             clock_source: timer::LSClockSource::APBClk,
             frequency: 24u32.kHz(),
         })
@@ -193,51 +245,71 @@ async fn main(spawner: Spawner) {
     // Define the display from the display interface and initialize it
     let mut delay = Delay::new();
 
-    let mut display = Builder::new(GC9A01, di)
+    let mut display = match Builder::new(GC9A01, di)
         .reset_pin(rst)
-        .display_size(240, 240)
+        // the model's own size, 240x240 for the GC9A01
         .color_order(mipidsi::options::ColorOrder::Bgr)
         .invert_colors(ColorInversion::Inverted)
         // .orientation(Orientation::new().rotate(Rotation::Deg180))
         .init(&mut delay)
-        .unwrap();
+    {
+        Ok(display) => display,
+        Err(e) => {
+            // panicking here would reboot into the same failure, so stay up and keep telling why
+            halt("display init failed — check DC/RST/CS wiring", &e).await
+        }
+    };
 
-    // // Make the display all
-    // match display.set_tearing_effect(TearingEffect::Vertical) {
-    //     Ok(_) => log::info!("set_tearing_effect successful"),
-    //     Err(e) => log::info!("set_tearing_effect failed"),
-    // };
-    display.clear(Rgb565::WHITE).unwrap();
+    // Sync the frames on the panel TE line, to avoid tearing during animations
+    #[cfg(feature = "tearing-effect")]
+    let tearing_effect = {
+        match display.set_tearing_effect(TearingEffect::Vertical) {
+            Ok(_) => log::info!("set_tearing_effect successful"),
+            Err(e) => log::error!("set_tearing_effect failed: {:?}", e),
+        };
+        Some(Input::new(peripherals.GPIO23, esp_hal::gpio::Pull::None))
+    };
+    #[cfg(not(feature = "tearing-effect"))]
+    let tearing_effect = None;
+    if let Err(e) = display.clear(BOOT_CLEAR_COLOR) {
+        log::error!("display clear failed ({:?}) — check SCK/MOSI wiring", e);
+    }
     // display.clear(Rgb565::RED).unwrap();
 
-    let timg1 = TimerGroup::new(peripherals.TIMG1);
-    let mut rng = Rng::new(peripherals.RNG);
+    // the offline build never powers the radio up, time only comes from the RTC
+    #[cfg(not(feature = "offline"))]
+    let (stack, runner, controller) = {
+        let timg1 = TimerGroup::new(peripherals.TIMG1);
+        let mut rng = Rng::new(peripherals.RNG);
 
-    let init = &*singleton!(
-        esp_wifi::init(timg1.timer0, rng.clone(), peripherals.RADIO_CLK).unwrap(),
-        EspWifiController<'static>
-    );
+        let init = &*singleton!(
+            esp_wifi::init(timg1.timer0, rng.clone(), peripherals.RADIO_CLK).unwrap(),
+            EspWifiController<'static>
+        );
 
-    let wifi = peripherals.WIFI;
-    let (wifi_interface, controller): (
-        esp_wifi::wifi::WifiDevice<'_, esp_wifi::wifi::WifiStaDevice>,
-        esp_wifi::wifi::WifiController<'_>,
-    ) = esp_wifi::wifi::new_with_mode(&init, wifi, esp_wifi::wifi::WifiStaDevice).unwrap();
-
-    let config = embassy_net::Config::dhcpv4(Default::default());
-
-    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
-
-    // Init network stack
-    let (stack, runner): (
-        embassy_net::Stack<'_>,
-        embassy_net::Runner<'_, esp_wifi::wifi::WifiDevice<'_, esp_wifi::wifi::WifiStaDevice>>,
-    ) = embassy_net::new(
-        wifi_interface,
-        config,
-        singleton!(StackResources::<3>::new(), StackResources<3>),
-        seed,
-    );
+        let wifi = peripherals.WIFI;
+        let (wifi_interface, controller): (
+            esp_wifi::wifi::WifiDevice<'_, esp_wifi::wifi::WifiStaDevice>,
+            esp_wifi::wifi::WifiController<'_>,
+        ) = esp_wifi::wifi::new_with_mode(&init, wifi, esp_wifi::wifi::WifiStaDevice).unwrap();
+
+        let config = embassy_net::Config::dhcpv4(Default::default());
+
+        let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+
+        // Init network stack
+        let (stack, runner): (
+            embassy_net::Stack<'_>,
+            embassy_net::Runner<'_, esp_wifi::wifi::WifiDevice<'_, esp_wifi::wifi::WifiStaDevice>>,
+        ) = embassy_net::new(
+            wifi_interface,
+            config,
+            singleton!(StackResources::<3>::new(), StackResources<3>),
+            seed,
+        );
+
+        (stack, runner, controller)
+    };
 
     let i2c = I2c::new(peripherals.I2C0, esp_hal::i2c::master::Config::default())
         .ok()
@@ -250,42 +322,100 @@ async fn main(spawner: Spawner) {
         ds323x::interface::I2cInterface<I2c<'_, esp_hal::Blocking>>,
         ds323x::ic::DS3231,
     > = Ds323x::new_ds3231(i2c);
+    #[cfg(feature = "rtc-32khz")]
+    {
+        if let Err(e) = ds3231.enable_32khz_output() {
+            log::error!("could not enable DS3231 32kHz output: {:?}", e);
+        }
+        let pcnt = esp_hal::pcnt::Pcnt::new(peripherals.PCNT);
+        let input = Input::new(peripherals.GPIO22, esp_hal::gpio::Pull::Up);
+        let timebase = Ds3231Timebase::new(pcnt.unit0, input);
+        spawner.spawn(run_timebase(timebase)).unwrap();
+    }
     // ds1307.set_running().ok();
 
     // let datetime = ds1307.datetime().unwrap();
     // log::info!("DS1307: {}", ds1307.running().ok().unwrap());
-    let board = Board::new().backlight(channel0).rtc(RtcRelated {
-        ds1307: Mutex::new(ds3231),
+    let board = Board::new().backlight(channel0).rtc(RtcRelated::new(
+        ds3231,
         rtc,
-        temperature_sensor: tsen,
-    });
+        tsen,
+        TIME_SOURCE,
+    ));
 
-    let window = MinimalSoftwareWindow::new(RepaintBufferType::ReusedBuffer);
-    window.set_size(slint::PhysicalSize::new(
-        DISPLAY_WIDTH as u32,
-        DISPLAY_HEIGHT as u32,
+    let mut settings_store = FlashSettingsStore::new();
+    settings::init(settings_store.load().unwrap_or_default());
+    log::info!("settings: {:?}", settings::current());
+    if let Some(table) = settings_store.load_sky_table() {
+        log::info!("sky table of {} entries loaded from flash", table.len());
+        esp32_mipidsi_clock::sky::set_sky_table(table);
+    }
+    spawner.spawn(persist_settings(settings_store)).unwrap();
+    controller::ROTATION_REQUEST.signal(controller::rotation_from_quarters(
+        settings::current().rotation,
     ));
+
+    let (bl, board) = board.backlight_peripheral();
+    let (rtc, board) = board.rtc_peripheral();
+    let rtc_rc = Rc::new(rtc);
+
+    #[cfg(not(feature = "offline"))]
+    let mut wifi_controller = EspEmbassyWifiController::new(controller);
+
+    let mut common = Flex::new(peripherals.GPIO9);
+    let mut first_struct = Input::new(peripherals.GPIO0, esp_hal::gpio::Pull::Up);
+    let mut second_struct = Input::new(peripherals.GPIO1, esp_hal::gpio::Pull::Up);
+    let mut third_struct = Input::new(peripherals.GPIO2, esp_hal::gpio::Pull::Up);
+
+    if self_test_requested(&mut common, &first_struct) {
+        #[cfg(not(feature = "offline"))]
+        self_test(&mut display, &rtc_rc, &mut wifi_controller).await;
+        #[cfg(feature = "offline")]
+        self_test(&mut display, &rtc_rc).await;
+    }
+
+    // the panel is the single source of truth for the window size
+    let display_size = display.bounding_box().size;
+    let window = MinimalSoftwareWindow::new(RepaintBufferType::ReusedBuffer);
+    window.set_size(slint::PhysicalSize::new(display_size.width, display_size.height));
     let backend = Box::new(EspEmbassyBackend::new(window.clone()));
 
     slint::platform::set_platform(backend).expect("backend already initialized");
     log::info!("slint gui setup complete");
 
     // TASK: run the gui render loop
-    spawner.spawn(render_loop(window, display)).unwrap();
-    let (bl, board) = board.backlight_peripheral();
-    let (rtc, board) = board.rtc_peripheral();
-    let rtc_rc = Rc::new(rtc);
+    spawner
+        .spawn(render_loop(window, display, tearing_effect))
+        .unwrap();
+    // let the display clear and first frame go before the WiFi starts
+    tasks::boot_stagger().await;
+
+    // a reset (e.g. the panic handler) leaves the battery backed DS3231 running: the clock must
+    // come back on its time, not on the epoch
+    let boot_time = rtc_rc.get_date_time().await;
+    let trusted = rtc_rc.is_trusted().await;
+    log::info!(
+        "time at boot: {} from {} (trusted: {})",
+        boot_time,
+        rtc_rc.source().name(),
+        trusted
+    );
+    if trusted && boot_time.year() < controller::MIN_PLAUSIBLE_YEAR {
+        log::error!("the RTC vouches for {}, its time was lost", boot_time);
+    }
 
-    let _ = spawner
-        .spawn(run_wifi_controller(EspEmbassyWifiController::new(
-            controller,
-        )))
-        .ok();
+    #[cfg(not(feature = "offline"))]
+    let _ = spawner.spawn(run_wifi_controller(wifi_controller)).ok();
+    #[cfg(not(feature = "offline"))]
     let _ = spawner.spawn(net_task(runner)).ok();
+    #[cfg(not(feature = "offline"))]
+    tasks::boot_stagger().await;
 
+    #[cfg(not(feature = "offline"))]
     let ntp_client = NtpClient::new(stack);
     // let dns_socket = singleton!( DnsSocket::new(stack), DnsSocket<'_>);
 
+    #[cfg(not(feature = "offline"))]
     let state: &TcpClientState<1, 4096, 4096> =
         singleton!( TcpClientState::<1, 4096, 4096>::new(), TcpClientState<1, 4096, 4096>);
 
@@ -312,18 +442,29 @@ async fn main(spawner: Spawner) {
     // .await;
 
     // let _ = spawner.spawn(print_stats()).unwrap();
-    let _ = spawner.spawn(fade_screen(bl, rtc_rc.clone())).unwrap();
-    let _ = spawner.spawn(run_ntp_client(ntp_client));
     // let _ = spawner.spawn(run_weather(client));
-    let _ = spawner.spawn(update_rtc_with_ntp(rtc_rc.clone()));
-    let _ = spawner.spawn(wifi_status_task(stack));
-
-    let _ = spawner.spawn(update_timer(rtc_rc.clone()));
-
-    let mut common = Flex::new(peripherals.GPIO9);
-    let mut first_struct = Input::new(peripherals.GPIO0, esp_hal::gpio::Pull::Up);
-    let mut second_struct = Input::new(peripherals.GPIO1, esp_hal::gpio::Pull::Up);
-    let mut third_struct = Input::new(peripherals.GPIO2, esp_hal::gpio::Pull::Up);
+    #[cfg(not(feature = "offline"))]
+    let status_stack = Some(stack);
+    #[cfg(feature = "offline")]
+    let status_stack = None;
+    // nothing to connect to, hide the wifi indicator
+    #[cfg(feature = "offline")]
+    controller::send_action(Action::WifiStateUpdate(slint_generated::WifiState::OK));
+
+    // esp_println writes the logs on the same USB serial, only the receiving half is needed
+    let (serial_rx, _) = UsbSerialJtag::new(peripherals.USB_DEVICE)
+        .into_async()
+        .split();
+
+    let tasks = ClockTasks::default()
+        .backlight(bl)
+        .status(status_stack)
+        .temperature()
+        .timer()
+        .serial_cli(serial_rx);
+    #[cfg(not(feature = "offline"))]
+    let tasks = tasks.ntp(ntp_client, stack);
+    tasks.spawn(&spawner, rtc_rc.clone()).await;
 
     let _ = spawner.spawn(poll_button(
         rtc_rc.clone(),
@@ -333,6 +474,21 @@ async fn main(spawner: Spawner) {
         third_struct,
     ));
 
+    #[cfg(feature = "encoder")]
+    {
+        // A/B channels of the rotary encoder, its push button is wired on the keyboard matrix.
+        // SAFETY: the encoder GPIOs are not taken from `peripherals` anywhere else
+        let (pin_a, pin_b) = unsafe {
+            (
+                esp_hal::gpio::GpioPin::<ENCODER_A_GPIO>::steal(),
+                esp_hal::gpio::GpioPin::<ENCODER_B_GPIO>::steal(),
+            )
+        };
+        let encoder_a = Input::new(pin_a, esp_hal::gpio::Pull::Up);
+        let encoder_b = Input::new(pin_b, esp_hal::gpio::Pull::Up);
+        spawner.spawn(poll_encoder(encoder_a, encoder_b)).unwrap();
+    }
+
     let recipe = Recipe::new().unwrap();
 
     recipe.show().expect("unable to show main window");
@@ -342,6 +498,88 @@ async fn main(spawner: Spawner) {
     controller.run().await;
 }
 
+/// Parks the firmware on an unrecoverable boot error, logging the reason periodically
+/// instead of panicking into a silent reboot loop.
+async fn halt(reason: &str, error: &dyn core::fmt::Debug) -> ! {
+    loop {
+        log::error!("{} ({:?})", reason, error);
+        Timer::after(Duration::from_secs(5)).await;
+    }
+}
+
+/// Holding S1 at power on runs `self_test` before the clock starts.
+fn self_test_requested(common: &mut Flex<'static>, s1: &Input<'static>) -> bool {
+    common.set_as_output();
+    common.set_low();
+    // let the pull-up settle on the freshly driven matrix
+    Delay::new().delay_millis(1);
+    s1.is_low()
+}
+
+/// Bring-up check of each subsystem, one after the other, with a pass/fail line per item
+/// under color bars. The results stay up for `SELF_TEST_RESULTS_DISPLAY`, then the clock
+/// starts as usual.
+async fn self_test(
+    display: &mut DisplayImpl<GC9A01>,
+    rtc: &RTCUtils,
+    #[cfg(not(feature = "offline"))] wifi: &mut EspEmbassyWifiController<'static>,
+) {
+    log::info!("self test");
+    let _ = display.clear(Rgb565::BLACK);
+    let colors = [
+        Rgb565::RED,
+        Rgb565::GREEN,
+        Rgb565::BLUE,
+        Rgb565::CYAN,
+        Rgb565::MAGENTA,
+        Rgb565::YELLOW,
+    ];
+    let bar_width = display.bounding_box().size.width / colors.len() as u32;
+    let bars = colors.iter().enumerate().try_for_each(|(i, color)| {
+        let origin = Point::new(i as i32 * bar_width as i32, 30);
+        display.fill_solid(&Rectangle::new(origin, Size::new(bar_width, 60)), *color)
+    });
+
+    let mut results = Vec::new();
+    results.push(match bars {
+        Ok(_) => (true, String::from("display")),
+        Err(e) => (false, format!("display {:?}", e)),
+    });
+
+    let time = rtc.get_date_time().await;
+    let trusted = rtc.is_trusted().await;
+    let rtc_ok = trusted && time.year() >= controller::MIN_PLAUSIBLE_YEAR;
+    results.push((
+        rtc_ok,
+        format!("{} {}", rtc.source().name(), time.format("%H:%M:%S")),
+    ));
+
+    let celsius = rtc.temperature_sensor.get_temperature().to_celsius();
+    results.push((
+        SELF_TEST_TEMPERATURE_RANGE.contains(&celsius),
+        format!("temp {:.1}C", celsius),
+    ));
+
+    #[cfg(not(feature = "offline"))]
+    results.push(match wifi.scan().await {
+        Ok(count) => (count > 0, format!("wifi {} APs", count)),
+        Err(e) => (false, format!("wifi {:?}", e)),
+    });
+    #[cfg(feature = "offline")]
+    results.push((true, String::from("wifi off")));
+
+    for (i, (ok, label)) in results.iter().enumerate() {
+        log::info!("self test: {} {}", if *ok { "pass" } else { "FAIL" }, label);
+        let style = MonoTextStyle::new(&FONT_9X15, if *ok { Rgb565::GREEN } else { Rgb565::RED });
+        let position = Point::new(40, 115 + i as i32 * 20);
+        let line = format!("{} {}", if *ok { "ok  " } else { "FAIL" }, label);
+        let _ = Text::new(&line, position, style).draw(display);
+    }
+
+    Timer::after(SELF_TEST_RESULTS_DISPLAY).await;
+    let _ = display.clear(BOOT_CLEAR_COLOR);
+}
+
 #[embassy_executor::task]
 async fn poll_button(
     rtc: Rc<RTCUtils>,
@@ -363,7 +601,21 @@ async fn poll_button(
         debouncer2.update(second_struct.is_low());
         debouncer3.update(third_struct.is_low());
 
-        if (debouncer1.is_high() || debouncer2.is_high() || debouncer3.is_high()) {
+        let pressed = debouncer1.is_high() || debouncer2.is_high() || debouncer3.is_high();
+        if pressed {
+            tasks::record_interaction();
+        }
+        if pressed && !controller::screen_is_on() {
+            // the first press only wakes the screen up: wait for the release so it isn't
+            // taken as a command too, and start over from released debouncers
+            controller::send_action(Action::ScreenPower(true));
+            while first_struct.is_low() || second_struct.is_low() || third_struct.is_low() {
+                Timer::after(Duration::from_millis(10)).await;
+            }
+            debouncer1 = debounce_stateful_2(false);
+            debouncer2 = debounce_stateful_2(false);
+            debouncer3 = debounce_stateful_2(false);
+        } else if pressed {
             common.set_as_input(esp_hal::gpio::Pull::Up);
 
             Timer::after(Duration::from_millis(10)).await;
@@ -381,56 +633,147 @@ async fn poll_button(
                 s5,
             );
 
-            if (s1 && common_input && debouncer1.is_high()) {
-                log::info!("S2");
-            } else if (!s1 && !common_input && debouncer1.is_high()) {
-                log::info!("S1 et S2");
-            } else if (!s1 && common_input && debouncer1.is_high()) {
-                log::info!("S1");
-            }
-
-            if (s3 && common_input && debouncer2.is_high()) {
-                log::info!("S3");
-            } else if (!s3 && !common_input && debouncer2.is_high()) {
-                log::info!("S3 et S4");
-            } else if (!s3 && common_input && debouncer2.is_high()) {
-                log::info!("S4");
-            }
-
-            if (s5 && common_input && debouncer3.is_high()) {
-                let current_time = rtc.get_date_time().await.with_timezone(&Paris);
-
-                controller::send_action(Action::StartCountDown(current_time, 120));
-            } else if (!s5 && !common_input && debouncer3.is_high()) {
-                log::info!("S5 et S6");
-            } else if (!s5 && common_input && debouncer3.is_high()) {
-                log::info!("S6");
+            // each line is shared by two buttons, the common line tells them apart
+            let lines = [
+                (debouncer1.is_high(), s1, &first_struct),
+                (debouncer2.is_high(), s3, &second_struct),
+                (debouncer3.is_high(), s5, &third_struct),
+            ];
+            for ((down, line_high, line), buttons) in lines.into_iter().zip(buttons::MATRIX_LINES) {
+                if !down {
+                    continue;
+                }
+                let Some(button) = Button::decode(line_high, common_input, buttons) else {
+                    continue;
+                };
+                let press = if BUTTON_MAP.has_long_press(button) {
+                    common.set_as_output();
+                    common.set_low();
+                    let pressed_at = Instant::now();
+                    let long_press = Duration::from_millis(buttons::LONG_PRESS_MS);
+                    while line.is_low() && pressed_at.elapsed() < long_press {
+                        Timer::after(BUTTON_IDLE_POLL).await;
+                    }
+                    if pressed_at.elapsed() < long_press {
+                        Press::SHORT
+                    } else {
+                        Press::LONG
+                    }
+                } else {
+                    Press::SHORT
+                };
+                let command = BUTTON_MAP.command(button, press);
+                log::info!("{:?} {:?}: {:?}", button, press, command);
+                if let Some(action) = command_action(command, &rtc).await {
+                    controller::send_action(action);
+                }
             }
             Timer::after(Duration::from_millis(100)).await;
         } else {
-            Timer::after(Duration::from_millis(1)).await;
+            // a press starting is sampled quickly so the debouncers settle fast
+            let pressing = first_struct.is_low() || second_struct.is_low() || third_struct.is_low();
+            Timer::after(if pressing {
+                BUTTON_DEBOUNCE_POLL
+            } else {
+                BUTTON_IDLE_POLL
+            })
+            .await;
         };
     }
 }
 
+/// What the buttons do, see `buttons::ButtonMap`.
+const BUTTON_MAP: ButtonMap = buttons::DEFAULT_BUTTON_MAP;
+
+/// The action of a button command, given the current state, `None` when it has nothing to do.
+async fn command_action(command: Option<ButtonCommand>, rtc: &RTCUtils) -> Option<Action> {
+    match command? {
+        ButtonCommand::NEXT_SKY_THEME => Some(Action::NextSkyTheme),
+        ButtonCommand::TOGGLE_DEMO => Some(Action::SetTimeScale(toggled_time_scale())),
+        ButtonCommand::SCREEN_OFF => Some(Action::ScreenPower(false)),
+        ButtonCommand::TOGGLE_STATUS_PAGE => Some(Action::ToggleStatusPage),
+        ButtonCommand::ROTATE => Some(Action::SetRotation(controller::next_rotation())),
+        ButtonCommand::TOGGLE_HIGH_CONTRAST => Some(Action::SetAccessibilityMode(
+            !settings::current().high_contrast,
+        )),
+        ButtonCommand::COUNTDOWN if controller::alarm_ringing() => {
+            Some(Action::SnoozeAlarm(controller::SNOOZE_MINUTES))
+        }
+        ButtonCommand::COUNTDOWN => {
+            let timezone = settings::current().timezone;
+            let current_time = rtc.get_date_time().await.with_timezone(&timezone);
+            Some(Action::StartCountDown(current_time, controller::countdown_preset()))
+        }
+        ButtonCommand::DISMISS_ALARM => {
+            controller::alarm_ringing().then_some(Action::DismissCountDown)
+        }
+        ButtonCommand::TOGGLE_FRAME_STATS => Some(Action::ToggleFrameStats),
+        ButtonCommand::TOGGLE_TIME_ADJUST => Some(Action::ToggleTimeAdjust),
+    }
+}
+
+/// Switches between real time and the "demo day".
+fn toggled_time_scale() -> f32 {
+    if controller::time_scale() == 1.0 {
+        controller::DEMO_TIME_SCALE
+    } else {
+        1.0
+    }
+}
+
+/// Decodes a quadrature rotary encoder into `Action::Rotate` deltas, one per detent. The task
+/// sleeps on the A channel edges instead of polling it.
+#[cfg(feature = "encoder")]
+#[embassy_executor::task]
+async fn poll_encoder(mut encoder_a: Input<'static>, encoder_b: Input<'static>) {
+    loop {
+        // A falling edge gives the detent, B tells the direction
+        encoder_a.wait_for_falling_edge().await;
+        // a bounce of the contacts is over before ENCODER_DEBOUNCE, a detent still holds A low
+        Timer::after(ENCODER_DEBOUNCE).await;
+        if encoder_a.is_low() {
+            let delta = if encoder_b.is_high() { 1 } else { -1 };
+            log::debug!("encoder: {}", delta);
+            controller::send_action(Action::Rotate(delta));
+            encoder_a.wait_for_high().await;
+        }
+    }
+}
+
+#[cfg(not(feature = "offline"))]
 #[embassy_executor::task]
 async fn run_wifi_controller(mut wifi_controller: EspEmbassyWifiController<'static>) {
     wifi_controller.connection().await;
 }
 
+#[cfg(not(feature = "offline"))]
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static, WifiStaDevice>>) {
     runner.run().await
 }
 
 #[embassy_executor::task]
-async fn render_loop(window: Rc<MinimalSoftwareWindow>, display: DisplayImpl<GC9A01>) {
+async fn render_loop(
+    window: Rc<MinimalSoftwareWindow>,
+    display: DisplayImpl<GC9A01>,
+    mut tearing_effect: Option<Input<'static>>,
+) {
     // let display = displayRef;
 
+    // one line of the widest orientation, see DrawBuffer
+    let size = display.bounding_box().size;
+    let mut line = vec![FramePixel::default(); size.width.max(size.height) as usize];
     let mut buffer_provider = DrawBuffer {
         display: display,
-        buffer: &mut [slint::platform::software_renderer::Rgb565Pixel(0); 240],
+        buffer: &mut line,
     };
+
+    // drawing time of the last frames, for the frame stats overlay
+    let mut frame_times = [0_u64; FRAME_PROFILER_WINDOW];
+    let mut frames_drawn: usize = 0;
+    let mut frames_drawn_at_report: usize = 0;
+    let mut last_report = Instant::now();
+    let mut animations = AnimationTracker::default();
     loop {
         log::trace!("{} - slint drawing start!", Instant::now().as_millis());
 
@@ -482,10 +825,60 @@ async fn render_loop(window: Rc<MinimalSoftwareWindow>, display: DisplayImpl<GC9
         //     }
         // }
         // window.try_dispatch_event(event)
+        if let Some(te) = tearing_effect.as_mut() {
+            // start right after the panel refreshed, to stay ahead of its scan line.
+            // The timeout keeps rendering going if TE is not wired.
+            if with_timeout(
+                Duration::from_millis(TEARING_EFFECT_TIMEOUT_MS),
+                te.wait_for_rising_edge(),
+            )
+            .await
+            .is_err()
+            {
+                log::warn!("no TE edge in {}ms", TEARING_EFFECT_TIMEOUT_MS);
+            }
+        }
+        if let Some(rotation) = controller::ROTATION_REQUEST.try_take() {
+            let orientation = Orientation::new().rotate(rotation);
+            match buffer_provider.display.set_orientation(orientation) {
+                Ok(_) => {
+                    // the display swaps its dimensions by itself on 90/270° rotations
+                    let size = buffer_provider.display.bounding_box().size;
+                    // no touch controller yet, it would need the same transform here
+                    window.set_size(slint::PhysicalSize::new(size.width, size.height));
+                    window.request_redraw();
+                }
+                Err(e) => log::error!("could not rotate display: {:?}", e),
+            }
+        }
         let dirty = window.draw_if_needed(|renderer| {
             renderer.render_by_line(&mut buffer_provider);
         });
         let total = time::now() - start;
+        if dirty {
+            frame_times[frames_drawn % FRAME_PROFILER_WINDOW] = total.to_micros();
+            frames_drawn += 1;
+        }
+        let average_us = average_frame_time_us(&frame_times, frames_drawn);
+        if controller::frame_stats_visible() && last_report.elapsed().as_millis() >= 1000 {
+            let fps = (frames_drawn - frames_drawn_at_report) as f32 * 1000.0
+                / last_report.elapsed().as_millis() as f32;
+            // sampled before sending the stats, which take a slot themselves
+            let queued_actions = controller::ACTION.len();
+            log::debug!(
+                "queues: {}/{} actions, redraw pending: {}",
+                queued_actions,
+                controller::ACTION_CHANNEL_DEPTH,
+                controller::REDRAW.is_pending()
+            );
+            controller::send_action(Action::FrameStats(controller::FrameStats {
+                frame_time_ms: average_us as f32 / 1000.0,
+                fps,
+                queued_actions,
+            }));
+            last_report = Instant::now();
+            frames_drawn_at_report = frames_drawn;
+        }
         log::trace!(
             "{} - slint drawing time {}, active anims: {}, dirty: {}",
             Instant::now().as_millis(),
@@ -493,27 +886,39 @@ async fn render_loop(window: Rc<MinimalSoftwareWindow>, display: DisplayImpl<GC9
             window.has_active_animations(),
             dirty
         );
-        if !window.has_active_animations() {
+        let animating = window.has_active_animations();
+        if let Some(idle) = CPU_CLOCK_IDLE {
+            let busy = animating && controller::screen_is_on();
+            cpu_clock::set(if busy { CPU_CLOCK } else { idle });
+        }
+        if animations.stopped(animating) {
+            log::trace!("animations stopped, drawing the final frame");
+            window.request_redraw();
+            continue;
+        }
+        if !animating {
             if let Some(duration) = slint::platform::duration_until_next_timer_update() {
                 let millis = duration.as_millis().try_into().unwrap();
                 log::trace!("will sleep for {}ms", millis);
                 select(
-                    controller::refresh_screen(),
+                    controller::REDRAW.wait(),
                     Timer::after(Duration::from_millis(millis)),
                 )
                 .await;
             } else {
                 // https://github.com/slint-ui/slint/discussions/3994
                 log::trace!(
-                    "{} - will sleep until refresh_screen asked",
+                    "{} - will sleep until a redraw is requested",
                     Instant::now().as_millis()
                 );
-                let _ = controller::refresh_screen().await;
+                controller::REDRAW.wait().await;
                 // Timer::after(Duration::from_millis(10)).await;
-                log::trace!("{} - refresh_screen asked", Instant::now().as_millis());
+                log::trace!("{} - redraw requested", Instant::now().as_millis());
             }
         } else {
-            let pause_for_target_fps = SLINT_FRAME_DURATION_MS as i32 - total.to_millis() as i32;
+            // paced on the recent average rather than this frame alone, whose drawing time
+            // swings with what changed: the sleep settles instead of alternating long and short
+            let pause_for_target_fps = SLINT_FRAME_DURATION_MS as i32 - (average_us / 1000) as i32;
 
             if (pause_for_target_fps > 0) {
                 log::trace!(
@@ -530,28 +935,10 @@ async fn render_loop(window: Rc<MinimalSoftwareWindow>, display: DisplayImpl<GC9
     }
 }
 
-/** A task to prove that we can do other things that render_loops */
-#[embassy_executor::task]
-async fn fade_screen(bl: LedChannel, rtc: Rc<RTCUtils>) {
-    loop {
-        let d = rtc.get_date_time().await.with_timezone(&Paris);
-        let mut bl_level = 5;
-        if (d.hour() > 8 && d.hour() < 20) {
-            bl_level = 100;
-        } else if (d.hour() >= 20 && d.hour() < 21) {
-            bl_level = 30;
-        }
-        bl.set_duty(bl_level).unwrap();
-        log::trace!("Setting backlight to {}", bl_level);
-        Timer::after_secs(10).await;
-        // Timer::after_millis(10).await;
-        // bl.set_duty(bl_level).unwrap();
-        // if increase {
-        //     bl_level = bl_level + 1;
-        // } else {
-        //     bl_level = bl_level - 1;
-        // }
-    }
+/// Average drawing time of the last `FRAME_PROFILER_WINDOW` frames drawn, in microseconds.
+fn average_frame_time_us(frame_times: &[u64; FRAME_PROFILER_WINDOW], frames_drawn: usize) -> u64 {
+    let samples = frames_drawn.min(FRAME_PROFILER_WINDOW).max(1);
+    frame_times.iter().take(samples).sum::<u64>() / samples as u64
 }
 
 #[embassy_executor::task]
@@ -564,46 +951,15 @@ async fn print_stats() {
     }
 }
 
+#[cfg(feature = "rtc-32khz")]
 #[embassy_executor::task]
-async fn wifi_status_task(stack: Stack<'static>) {
-    loop {
-        if (stack.is_link_up()) {
-            if (stack.is_config_up()) {
-                controller::send_action(Action::WifiStateUpdate(slint_generated::WifiState::OK));
-            } else {
-                controller::send_action(Action::WifiStateUpdate(
-                    slint_generated::WifiState::LINKUP,
-                ));
-            }
-        } else {
-            controller::send_action(Action::WifiStateUpdate(
-                slint_generated::WifiState::STARTING,
-            ));
-        }
-
-        // refresh_signal.signal(());
-        if (!stack.is_config_up()) {
-            Timer::after(Duration::from_millis(50)).await;
-        } else {
-            Timer::after(Duration::from_secs(10)).await;
-        }
-    }
-}
-#[embassy_executor::task]
-
-async fn update_rtc_with_ntp(rtc: Rc<RTCUtils>) {
-    loop {
-        let now = await_now().await;
-        info!("Update time ! {}", now);
-
-        rtc.set_date_time(now.to_utc()).await;
-        Timer::after(Duration::from_secs(10)).await;
-    }
+async fn run_timebase(timebase: Ds3231Timebase) {
+    timebase.run().await
 }
 
 #[embassy_executor::task]
-async fn run_ntp_client(ntp_client: NtpClient<'static>) {
-    ntp_client.run().await;
+async fn persist_settings(store: FlashSettingsStore) {
+    store.run().await
 }
 
 // #[embassy_executor::task]
@@ -618,42 +974,6 @@ async fn run_ntp_client(ntp_client: NtpClient<'static>) {
 //     }
 // }
 
-#[embassy_executor::task]
-async fn update_timer(rtc: Rc<RTCUtils>) {
-    let mut visible = true;
-    let mut last_value = 0;
-    let mut ticker = Ticker::every(Duration::from_millis(1000));
-    loop {
-        let current_time = rtc.get_date_time().await.with_timezone(&Paris);
-
-        let actual = current_time.second() / 10 % 10;
-        if (actual != last_value) {
-            visible = !visible;
-        }
-        last_value = actual;
-
-        controller::send_action(Action::MultipleActions(vec![
-            Action::ShowMonster(visible),
-            Action::UpdateTime(current_time),
-            // Action::TimeOfDayUpdate(tod, moon),
-        ]));
-
-        log::debug!(
-            "Setting visible monster: {} (actual: {}, last_value{}, current_time: {})",
-            visible,
-            actual,
-            last_value,
-            current_time
-        );
-        // trigger refresh
-        // refresh_signal.signal(());
-
-        // Double trigger
-
-        ticker.next().await;
-        // Timer::after_millis(10).await;
-    }
-}
 
 // pub struct ReqwlessHttpGetClient<'a> {
 //     client: HttpClient<'a, TcpClient<'a, 1, 4096, 4096>, DnsSocket<'a>>,
@@ -24,15 +24,16 @@ use std::{
     vec::Vec,
 };
 
-use chrono::{DateTime, Days, Local, TimeDelta, Utc};
-use chrono_tz::Europe::Paris;
+use chrono::{DateTime, Days, Local, NaiveDateTime, TimeDelta, Utc};
 use embassy_executor::{Executor, Spawner};
-use embassy_sync::blocking_mutex::{CriticalSectionMutex, Mutex};
+use ds323x::DateTimeAccess;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, CriticalSectionMutex, Mutex};
 use embassy_time::{Duration, Instant, Ticker, Timer};
 use embedded_graphics::prelude::Point;
 use esp32_mipidsi_clock::{
     controller::{self, Action, Controller, Hardware, WallClock},
     moon::Moon,
+    settings,
     slintplatform::EspEmbassyBackend,
 };
 use log::*;
@@ -70,16 +71,24 @@ static MINUTES_OFFSET: CriticalSectionMutex<RefCell<i64>> =
 struct HardwareSim {}
 impl Hardware for HardwareSim {}
 
-struct WallClockSim {}
-impl WallClock for WallClockSim {
-    async fn get_date_time(&self) -> chrono::DateTime<Utc> {
-        Local::now().with_timezone(&Utc)
+/// Simulated DS3231: like the chip it only knows a naive date time, so the simulator goes
+/// through the same `WallClock` impl (and naive/UTC handling) as the device.
+struct RtcSim {
+    offset: TimeDelta,
+}
+impl DateTimeAccess for RtcSim {
+    type Error = ();
+
+    fn datetime(&mut self) -> Result<NaiveDateTime, Self::Error> {
+        Ok(Utc::now().naive_utc() + self.offset)
     }
 
-    async fn set_date_time(&self, datetime: chrono::DateTime<Utc>) {
-        ()
+    fn set_datetime(&mut self, datetime: &NaiveDateTime) -> Result<(), Self::Error> {
+        self.offset = *datetime - Utc::now().naive_utc();
+        Ok(())
     }
 }
+type WallClockSim = embassy_sync::mutex::Mutex<CriticalSectionRawMutex, RtcSim>;
 
 fn main() {
     env_logger::builder()
@@ -197,6 +206,37 @@ fn sdl2_render_loop(
                         slint_generated::WifiState::OK,
                     ));
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => controller::send_action(Action::NextSkyTheme),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => controller::send_action(Action::ToggleFrameStats),
+                Event::KeyDown {
+                    keycode: Some(Keycode::H),
+                    ..
+                } => controller::send_action(Action::SetAccessibilityMode(
+                    !settings::current().high_contrast,
+                )),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => controller::send_action(Action::SetTimeScale(
+                    if controller::time_scale() == 1.0 {
+                        controller::DEMO_TIME_SCALE
+                    } else {
+                        1.0
+                    },
+                )),
+                Event::MouseWheel { y, .. } => {
+                    controller::send_action(Action::Rotate(y.clamp(-1, 1) as i8))
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    ..
+                } => controller::send_action(Action::ToggleTimeAdjust),
                 Event::KeyDown {
                     keycode: Some(Keycode::F10),
                     ..
@@ -230,9 +270,21 @@ fn sdl2_render_loop(
                 } => controller::send_action(Action::StartCountDown(
                     DateTime::from_timestamp(Local::now().timestamp(), 0)
                         .unwrap()
-                        .with_timezone(&Paris),
+                        .with_timezone(&settings::current().timezone),
                     10,
                 )),
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => controller::send_action(Action::RefreshMoon),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Z),
+                    ..
+                } => controller::send_action(Action::SnoozeAlarm(controller::SNOOZE_MINUTES)),
+                Event::KeyDown {
+                    keycode: Some(Keycode::X),
+                    ..
+                } => controller::send_action(Action::DismissCountDown),
                 Event::MouseButtonDown {
                     timestamp: _timestamp,
                     window_id: _window_id,
@@ -345,7 +397,9 @@ async fn main_task(
     info!("press LEFT SHIFT to simulate a hardware button press");
 
     let hardware = HardwareSim {};
-    let wall_clock = Rc::new(WallClockSim {});
+    let wall_clock = Rc::new(WallClockSim::new(RtcSim {
+        offset: TimeDelta::zero(),
+    }));
 
     let _ = spawner.spawn(update_timer());
 
@@ -408,7 +462,7 @@ async fn update_timer() {
                 MINUTES_OFFSET.lock(|v| v.clone().into_inner()) as i64,
             ));
 
-        let actual = current_time.second() / 10 % 10;
+        let actual = current_time.second() / controller::monster_blink_period_secs();
         log::info!("actual: {}, last_value: {}", actual, last_value);
         if (actual != last_value) {
             visible = !visible;
@@ -417,7 +471,8 @@ async fn update_timer() {
         let mut point = Point { x: 125, y: 188 };
         let mut env = slint_generated::MonsterEnv::OUTSIDE;
 
-        let local_time = current_time.with_timezone(&Paris);
+        let timezone = settings::current().timezone;
+        let local_time = current_time.with_timezone(&timezone);
         if (local_time.hour() >= 20 || local_time.hour() < 8) {
             point = Point { x: 195, y: 143 };
             env = slint_generated::MonsterEnv::HOUSE;
@@ -438,7 +493,8 @@ async fn update_timer() {
             //     slint_generated::MonsterEnv::OUTSIDE,
             // ),
             Action::ShowMonster(visible),
-            Action::UpdateTime(current_time.with_timezone(&Paris)),
+            Action::ShowColon(!controller::BLINK_COLON || current_time.second() % 2 == 0),
+            Action::UpdateTime(controller::scaled_time(current_time).with_timezone(&timezone)),
         ]));
 
         log::debug!(
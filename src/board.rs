@@ -1,7 +1,13 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use chrono::{DateTime, Utc};
 use chrono_tz::Europe::Paris;
-use ds323x::Ds323x;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use ds323x::{DateTimeAccess, Ds323x};
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
 use esp_hal::{gpio::Output, i2c::master::I2c, rtc_cntl::Rtc, tsens::TemperatureSensor};
 
 use crate::controller::{Hardware, WallClock};
@@ -34,11 +40,23 @@ pub mod types {
         Output<'static>,
     >;
 }
+/// `&'static mut` to `$val`, kept in a static cell.
+///
+/// Should the cell already be initialized (`main` running again after a soft reset that kept
+/// the RAM content), the value is leaked on the heap instead: panicking would reset the chip
+/// into the same cell again, in a reboot loop.
 #[macro_export]
 macro_rules! singleton {
     ($val:expr, $T:ty) => {{
         static STATIC_CELL: ::static_cell::StaticCell<$T> = ::static_cell::StaticCell::new();
-        STATIC_CELL.init($val)
+        let val: $T = $val;
+        match STATIC_CELL.try_uninit() {
+            Some(cell) => cell.write(val),
+            None => {
+                ::log::warn!("{} already initialized, leaking a new one", stringify!($T));
+                ::alloc::boxed::Box::leak(::alloc::boxed::Box::new(val))
+            }
+        }
     }};
 }
 
@@ -50,16 +68,41 @@ pub struct SpiScreen<SPI> {
     pub spi: SPI,
 }
 
+pub type Ds3231 =
+    Ds323x<ds323x::interface::I2cInterface<I2c<'static, esp_hal::Blocking>>, ds323x::ic::DS3231>;
+
+/// Where the wall clock time comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeSource {
+    /// External DS3231 module, keeps the time on its battery while the board is unpowered.
+    DS3231,
+    /// RTC of the ESP itself, for boards without the module: it starts over at each boot and
+    /// only holds a valid time once NTP set it.
+    INTERNAL,
+}
+
+impl TimeSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeSource::DS3231 => "ds3231",
+            TimeSource::INTERNAL => "internal",
+        }
+    }
+}
+
+/// Clocks and sensors shared by the tasks through an `Rc`.
+///
+/// Locking discipline for the DS3231: every access takes the mutex for a single read or a
+/// single write sequence and releases it right away, nothing else is awaited while holding
+/// it. The lock is then held for an I2C transaction or two, so the per second reads of
+/// `update_timer` and the rare writes of `update_rtc_with_ntp` never wait long on each other.
 pub struct RtcRelated {
-    pub ds1307: Mutex<
-        NoopRawMutex,
-        Ds323x<
-            ds323x::interface::I2cInterface<I2c<'static, esp_hal::Blocking>>,
-            ds323x::ic::DS3231,
-        >,
-    >,
+    pub ds1307: Mutex<NoopRawMutex, Ds3231>,
     pub rtc: Rtc<'static>,
     pub temperature_sensor: TemperatureSensor<'static>,
+    source: TimeSource,
+    /// Whether the internal RTC was set since boot.
+    internal_set: AtomicBool,
 }
 
 pub struct Wifi {
@@ -213,23 +256,88 @@ impl<Backlight, ScreenSpi, Display, Wifi, RTCUtils>
         }
     }
 }
-use ds323x::DateTimeAccess;
+
+impl RtcRelated {
+    /// Uses `preferred` as time source, unless it is the DS3231 and the module doesn't answer
+    /// on the I2C bus, in which case the internal RTC takes over.
+    pub fn new(
+        mut ds3231: Ds3231,
+        rtc: Rtc<'static>,
+        temperature_sensor: TemperatureSensor<'static>,
+        preferred: TimeSource,
+    ) -> Self {
+        let source = match preferred {
+            TimeSource::DS3231 => match ds3231.datetime() {
+                Ok(_) => TimeSource::DS3231,
+                Err(e) => {
+                    log::warn!("no DS3231 found ({:?}), using the internal RTC", e);
+                    TimeSource::INTERNAL
+                }
+            },
+            TimeSource::INTERNAL => TimeSource::INTERNAL,
+        };
+        log::info!("time source: {}", source.name());
+        Self {
+            ds1307: Mutex::new(ds3231),
+            rtc,
+            temperature_sensor,
+            source,
+            internal_set: AtomicBool::new(false),
+        }
+    }
+
+    pub fn source(&self) -> TimeSource {
+        self.source
+    }
+
+    /// False when the DS3231 oscillator stopped flag is set, e.g. after the battery died:
+    /// the time it holds is then meaningless until set again. The internal RTC is trusted once
+    /// set since boot.
+    pub async fn is_trusted(&self) -> bool {
+        if self.source == TimeSource::INTERNAL {
+            return self.internal_set.load(Ordering::Relaxed);
+        }
+        match self.ds1307.lock().await.has_been_stopped() {
+            Ok(stopped) => !stopped,
+            Err(e) => {
+                log::error!("could not read RTC status: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Signaled each time `RtcRelated` is set, so readers keeping the time it gave (see
+/// `tasks::update_timer`) read it again.
+pub static CLOCK_WRITTEN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
 impl WallClock for RtcRelated {
     async fn get_date_time(&self) -> chrono::DateTime<Utc> {
-        self.ds1307
-            .lock()
-            .await
-            .datetime()
-            .map(|m| m.and_utc())
-            .unwrap_or(DateTime::from_timestamp_nanos(0))
+        match self.source {
+            TimeSource::DS3231 => self.ds1307.get_date_time().await,
+            TimeSource::INTERNAL => self.rtc.current_time().and_utc(),
+        }
     }
 
     async fn set_date_time(&self, datetime: chrono::DateTime<Utc>) {
-        self.ds1307
-            .lock()
-            .await
-            .set_datetime(&datetime.naive_local())
-            .ok();
+        match self.source {
+            TimeSource::DS3231 => {
+                // a single lock, so no read sees the new time with the stopped flag still set
+                let mut ds3231 = self.ds1307.lock().await;
+                if let Err(e) = ds3231.set_datetime(&datetime.naive_utc()) {
+                    log::error!("could not set RTC to {}: {:?}", datetime, e);
+                    return;
+                }
+                // the time is valid again, see `is_trusted`
+                if let Err(e) = ds3231.clear_has_been_stopped_flag() {
+                    log::error!("could not clear RTC stopped flag: {:?}", e);
+                }
+            }
+            TimeSource::INTERNAL => {
+                self.rtc.set_current_time(datetime.naive_utc());
+                self.internal_set.store(true, Ordering::Relaxed);
+            }
+        }
+        CLOCK_WRITTEN.signal(());
     }
 }
@@ -1,30 +1,85 @@
 use crate::board::types::DisplayImpl;
 use embedded_graphics::{pixelcolor::raw::RawU16, prelude::RgbColor};
 use mipidsi::{interface::InterfacePixelFormat, models::Model};
+use slint::{
+    platform::software_renderer::{Rgb565Pixel, TargetPixel},
+    Rgb8Pixel,
+};
 
-pub struct DrawBuffer<'a, Display> {
+/// Ordered dithering thresholds, from 0 to 15.
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Renders slint line by line straight to the display.
+///
+/// `buffer` holds a single line: it must be at least as long as the widest line the window
+/// can render, i.e. the display width (or height when rotated by 90/270°). Shorter lines
+/// are skipped with an error rather than corrupting the display.
+///
+/// The pixel type picks the line format, see `LinePixel`: `Rgb565Pixel` lines are sent as
+/// is, `Rgb8Pixel` lines take half as much memory again and are dithered when sent.
+pub struct DrawBuffer<'a, Display, Pixel> {
     pub display: Display,
-    pub buffer: &'a mut [slint::platform::software_renderer::Rgb565Pixel],
+    pub buffer: &'a mut [Pixel],
+}
+
+/// Pixels a `DrawBuffer` line is rendered in, reduced to the panel's Rgb565 when sent.
+pub trait LinePixel: TargetPixel {
+    /// Panel color of the pixel at (`x`, `y`).
+    fn to_rgb565(&self, x: usize, y: usize) -> RawU16;
+}
+
+impl LinePixel for Rgb565Pixel {
+    fn to_rgb565(&self, _x: usize, _y: usize) -> RawU16 {
+        RawU16::new(self.0)
+    }
 }
 
-impl<M> slint::platform::software_renderer::LineBufferProvider
-    for &mut DrawBuffer<'_, DisplayImpl<M>>
+/// A Bayer pattern breaks up the banding of the sky gradients for a few operations per pixel.
+/// The pattern only depends on the pixel position, so it is stable from frame to frame.
+impl LinePixel for Rgb8Pixel {
+    fn to_rgb565(&self, x: usize, y: usize) -> RawU16 {
+        // red and blue lose 3 bits, green 2: the threshold is scaled to what gets truncated
+        let threshold = BAYER_4X4[y % 4][x % 4];
+        let (rb_bias, g_bias) = (threshold / 2, threshold / 4);
+        let r = (self.r.saturating_add(rb_bias) >> 3) as u16;
+        let g = (self.g.saturating_add(g_bias) >> 2) as u16;
+        let b = (self.b.saturating_add(rb_bias) >> 3) as u16;
+        RawU16::new(r << 11 | g << 5 | b)
+    }
+}
+
+impl<M, Pixel> slint::platform::software_renderer::LineBufferProvider
+    for &mut DrawBuffer<'_, DisplayImpl<M>, Pixel>
 where
+    Pixel: LinePixel,
     M: Model,
     M::ColorFormat: InterfacePixelFormat<u8>,
     M::ColorFormat: RgbColor,
     M::ColorFormat: From<RawU16>,
 {
-    type TargetPixel = slint::platform::software_renderer::Rgb565Pixel;
+    type TargetPixel = Pixel;
 
     fn process_line(
         &mut self,
         line: usize,
         range: core::ops::Range<usize>,
-        render_fn: impl FnOnce(&mut [slint::platform::software_renderer::Rgb565Pixel]),
+        render_fn: impl FnOnce(&mut [Pixel]),
     ) {
+        if range.end > self.buffer.len() {
+            crate::log_throttled!(
+                1000,
+                log::Level::Error,
+                "line buffer too small: {} pixels, line {} needs {}",
+                self.buffer.len(),
+                line,
+                range.end
+            );
+            return;
+        }
         let buffer = &mut self.buffer[range.clone()];
-        log::debug!(
+        crate::log_throttled!(
+            1000,
+            log::Level::Debug,
             "Redraw l: {}, range: {}-{} ({})",
             line,
             range.start,
@@ -40,8 +95,87 @@ where
                 line as _,
                 (range.end - 1) as u16, // Range are inclusive /!\
                 line as u16,
-                buffer.iter().map(|x| RawU16::new(x.0).into()),
+                buffer
+                    .iter()
+                    .enumerate()
+                    .map(|(i, pixel)| pixel.to_rgb565(range.start + i, line).into()),
             )
             .unwrap();
     }
 }
+
+/// Panels that can only refresh as a whole, like e-paper: `FrameBuffer` hands them complete
+/// frames instead of pushing lines as they are rendered.
+pub trait FullFrameDisplay {
+    type Error: core::fmt::Debug;
+
+    /// Shows `frame`, rows of `width` pixels one after the other.
+    fn update_frame(&mut self, frame: &[Rgb565Pixel], width: usize) -> Result<(), Self::Error>;
+}
+
+/// Renders slint into a whole frame kept in memory, sent to the display by `flush`. For
+/// displays where each refresh is slow and visible (e-paper), the LCDs use `DrawBuffer`.
+///
+/// `frame` must hold `width` times the window height pixels, lines outside of it are skipped
+/// with an error.
+pub struct FrameBuffer<'a, Display> {
+    pub display: Display,
+    pub frame: &'a mut [Rgb565Pixel],
+    pub width: usize,
+    dirty: bool,
+}
+
+impl<'a, Display: FullFrameDisplay> FrameBuffer<'a, Display> {
+    pub fn new(display: Display, frame: &'a mut [Rgb565Pixel], width: usize) -> Self {
+        Self {
+            display,
+            frame,
+            width,
+            dirty: false,
+        }
+    }
+
+    /// Sends the frame to the display if something was rendered since the last flush, once
+    /// per frame at most. Returns whether the display was updated.
+    pub fn flush(&mut self) -> bool {
+        if !self.dirty {
+            return false;
+        }
+        self.dirty = false;
+        match self.display.update_frame(self.frame, self.width) {
+            Ok(_) => true,
+            Err(e) => {
+                log::error!("could not update the display: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
+impl<Display: FullFrameDisplay> slint::platform::software_renderer::LineBufferProvider
+    for &mut FrameBuffer<'_, Display>
+{
+    type TargetPixel = Rgb565Pixel;
+
+    fn process_line(
+        &mut self,
+        line: usize,
+        range: core::ops::Range<usize>,
+        render_fn: impl FnOnce(&mut [Rgb565Pixel]),
+    ) {
+        let start = line * self.width;
+        if range.end > self.width || start + range.end > self.frame.len() {
+            crate::log_throttled!(
+                1000,
+                log::Level::Error,
+                "frame buffer too small: {} pixels, line {} needs {}",
+                self.frame.len(),
+                line,
+                start + range.end
+            );
+            return;
+        }
+        render_fn(&mut self.frame[start + range.start..start + range.end]);
+        self.dirty = true;
+    }
+}
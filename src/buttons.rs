@@ -0,0 +1,117 @@
+//! What the hardware buttons do, as a table from a press to a command, so buttons can be
+//! reassigned without touching the matrix decoding of the firmware (see `poll_button` in the
+//! mcu binary).
+
+/// The six buttons of the keyboard matrix, and the pairs sharing a line pressed together.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    S1,
+    S2,
+    S1_S2,
+    S3,
+    S4,
+    S3_S4,
+    S5,
+    S6,
+    S5_S6,
+}
+
+impl Button {
+    /// Tells the buttons of a matrix line apart from the line and the common line levels,
+    /// read with both pulled up. `buttons` are the button read with the line low, the one
+    /// read with the line high, and both pressed together (both lines low).
+    pub fn decode(line_high: bool, common_high: bool, buttons: [Button; 3]) -> Option<Button> {
+        match (line_high, common_high) {
+            (true, true) => Some(buttons[1]),
+            (false, false) => Some(buttons[2]),
+            (false, true) => Some(buttons[0]),
+            (true, false) => None,
+        }
+    }
+}
+
+/// Buttons of each line of the matrix, in the order of `Button::decode`.
+pub const MATRIX_LINES: [[Button; 3]; 3] = [
+    [Button::S1, Button::S2, Button::S1_S2],
+    [Button::S4, Button::S3, Button::S3_S4],
+    [Button::S6, Button::S5, Button::S5_S6],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Press {
+    SHORT,
+    /// Held for `LONG_PRESS_MS` at least.
+    LONG,
+}
+
+/// A press held that long, in milliseconds, is a `Press::LONG`.
+pub const LONG_PRESS_MS: u64 = 1000;
+
+/// What a press does. Commands are resolved into an `Action` when the button is pressed, as
+/// some depend on the current state (time, alarm, settings).
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonCommand {
+    NEXT_SKY_THEME,
+    /// Switches between real time and the demo day.
+    TOGGLE_DEMO,
+    SCREEN_OFF,
+    TOGGLE_STATUS_PAGE,
+    /// Turns the display a quarter turn.
+    ROTATE,
+    TOGGLE_HIGH_CONTRAST,
+    /// Starts the preset countdown, or snoozes the alarm while it rings.
+    COUNTDOWN,
+    /// Silences a ringing alarm for good, does nothing otherwise.
+    DISMISS_ALARM,
+    TOGGLE_FRAME_STATS,
+    /// Switches the encoder between the countdown and setting the clock.
+    TOGGLE_TIME_ADJUST,
+}
+
+pub struct ButtonMap {
+    entries: &'static [(Button, Press, ButtonCommand)],
+}
+
+/// The default assignment of the buttons.
+pub const DEFAULT_BUTTON_MAP: ButtonMap = ButtonMap::new(&[
+    (Button::S1, Press::SHORT, ButtonCommand::NEXT_SKY_THEME),
+    (Button::S2, Press::SHORT, ButtonCommand::TOGGLE_DEMO),
+    (Button::S1_S2, Press::SHORT, ButtonCommand::SCREEN_OFF),
+    (Button::S3, Press::SHORT, ButtonCommand::TOGGLE_STATUS_PAGE),
+    (Button::S4, Press::SHORT, ButtonCommand::TOGGLE_HIGH_CONTRAST),
+    (Button::S3_S4, Press::SHORT, ButtonCommand::ROTATE),
+    (Button::S5, Press::SHORT, ButtonCommand::COUNTDOWN),
+    (Button::S5, Press::LONG, ButtonCommand::DISMISS_ALARM),
+    (Button::S6, Press::SHORT, ButtonCommand::TOGGLE_FRAME_STATS),
+    (Button::S6, Press::LONG, ButtonCommand::TOGGLE_TIME_ADJUST),
+]);
+
+impl ButtonMap {
+    pub const fn new(entries: &'static [(Button, Press, ButtonCommand)]) -> Self {
+        Self { entries }
+    }
+
+    /// The command of a press. A long press falls back on the short press command when it
+    /// has none of its own.
+    pub fn command(&self, button: Button, press: Press) -> Option<ButtonCommand> {
+        self.find(button, press).or_else(|| match press {
+            Press::LONG => self.find(button, Press::SHORT),
+            Press::SHORT => None,
+        })
+    }
+
+    /// Whether a long press of `button` does something else than a short one: only then is
+    /// the press timed, the others act as soon as the button goes down.
+    pub fn has_long_press(&self, button: Button) -> bool {
+        self.find(button, Press::LONG).is_some()
+    }
+
+    fn find(&self, button: Button, press: Press) -> Option<ButtonCommand> {
+        self.entries
+            .iter()
+            .find(|(b, p, _)| *b == button && *p == press)
+            .map(|(_, _, command)| *command)
+    }
+}
@@ -0,0 +1,184 @@
+//! Commands typed on the serial console, one per line, to tweak a clock in the field without
+//! reflashing it.
+//!
+//! Parsing is shared, the firmware reads the lines (see `tasks::serial_cli`) and runs the
+//! commands.
+
+use chrono_tz::Tz;
+
+use crate::controller::{self, Action, Weather};
+use crate::settings::{self, Settings};
+use crate::sky::NightSky;
+
+/// Usage printed by `help` and on errors.
+pub const HELP: &str = "commands: status | theme | moon | rotate | screen on|off \
+                        | contrast on|off | demo [scale] | preset <secs> | bright <0-100>|auto \
+                        | units c|f | night dark|moon \
+                        | weather clear|cloudy|rain|snow|none | birthday <month>-<day>|none \
+                        | tz <Area/City> | sync";
+
+/// Longest line accepted, longer ones are dropped.
+pub const MAX_LINE_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Handled by the controller like a button press.
+    Action(Action),
+    /// Ask for an NTP sync right away.
+    Sync,
+    /// Backlight level in percent, `None` goes back to the automatic level.
+    Brightness(Option<u8>),
+    Help,
+}
+
+pub fn parse(line: &str) -> Result<Command, &'static str> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Err("empty command");
+    };
+    match (command, words.next()) {
+        ("help", _) => Ok(Command::Help),
+        ("status", _) => Ok(Command::Action(Action::ToggleStatusPage)),
+        ("theme", _) => Ok(Command::Action(Action::NextSkyTheme)),
+        ("moon", _) => Ok(Command::Action(Action::RefreshMoon)),
+        ("rotate", _) => Ok(Command::Action(Action::SetRotation(
+            controller::next_rotation(),
+        ))),
+        ("screen", Some("on")) => Ok(Command::Action(Action::ScreenPower(true))),
+        ("screen", Some("off")) => Ok(Command::Action(Action::ScreenPower(false))),
+        ("screen", _) => Err("usage: screen on|off"),
+        ("contrast", Some("on")) => Ok(Command::Action(Action::SetAccessibilityMode(true))),
+        ("contrast", Some("off")) => Ok(Command::Action(Action::SetAccessibilityMode(false))),
+        ("contrast", _) => Err("usage: contrast on|off"),
+        ("demo", None) => Ok(Command::Action(Action::SetTimeScale(
+            controller::DEMO_TIME_SCALE,
+        ))),
+        ("demo", Some(scale)) => match scale.parse::<f32>() {
+            Ok(scale) if controller::is_valid_time_scale(scale) => {
+                Ok(Command::Action(Action::SetTimeScale(scale)))
+            }
+            _ => Err("usage: demo [scale], 1 for real time, up to 3600"),
+        },
+        ("preset", Some(secs)) => secs
+            .parse::<u8>()
+            .map(|secs| {
+                Command::Action(Action::UpdateSettings(Settings {
+                    countdown_preset: secs,
+                    ..settings::current()
+                }))
+            })
+            .map_err(|_| "usage: preset <secs>, up to 255"),
+        ("bright", Some("auto")) => Ok(Command::Brightness(None)),
+        ("bright", Some(level)) => match level.parse::<u8>() {
+            Ok(level) if level <= 100 => Ok(Command::Brightness(Some(level))),
+            _ => Err("usage: bright <0-100>|auto"),
+        },
+        ("weather", Some(conditions)) => match conditions {
+            "clear" => Ok(Some(Weather::CLEAR)),
+            "cloudy" => Ok(Some(Weather::CLOUDY)),
+            "rain" => Ok(Some(Weather::RAIN)),
+            "snow" => Ok(Some(Weather::SNOW)),
+            "none" => Ok(None),
+            _ => Err("usage: weather clear|cloudy|rain|snow|none"),
+        }
+        .map(|weather| Command::Action(Action::WeatherUpdate(weather))),
+        ("units", Some(unit @ ("c" | "f"))) => {
+            Ok(Command::Action(Action::UpdateSettings(Settings {
+                fahrenheit: unit == "f",
+                ..settings::current()
+            })))
+        }
+        ("units", _) => Err("usage: units c|f"),
+        ("night", Some("dark")) => Ok(Command::Action(Action::SetNightSky(NightSky::DARK))),
+        ("night", Some("moon")) => Ok(Command::Action(Action::SetNightSky(NightSky::MOONLIT))),
+        ("night", _) => Err("usage: night dark|moon"),
+        ("birthday", Some("none")) => Ok(Command::Action(Action::UpdateSettings(Settings {
+            birthday: None,
+            ..settings::current()
+        }))),
+        ("birthday", Some(date)) => parse_month_day(date)
+            .map(|birthday| {
+                Command::Action(Action::UpdateSettings(Settings {
+                    birthday: Some(birthday),
+                    ..settings::current()
+                }))
+            })
+            .ok_or("usage: birthday <month>-<day>|none"),
+        ("tz", Some(name)) => name
+            .parse::<Tz>()
+            .map(|timezone| {
+                Command::Action(Action::UpdateSettings(Settings {
+                    timezone,
+                    ..settings::current()
+                }))
+            })
+            .map_err(|_| "usage: tz <Area/City>, e.g. Europe/Paris"),
+        ("sync", _) => Ok(Command::Sync),
+        _ => Err(HELP),
+    }
+}
+
+/// Parses "12-31" into (12, 31).
+fn parse_month_day(date: &str) -> Option<(u8, u8)> {
+    let (month, day) = date.split_once('-')?;
+    let (month, day) = (month.parse::<u8>().ok()?, day.parse::<u8>().ok()?);
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then_some((month, day))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demo_scale() {
+        assert!(matches!(
+            parse("demo"),
+            Ok(Command::Action(Action::SetTimeScale(s))) if s == controller::DEMO_TIME_SCALE
+        ));
+        assert!(matches!(
+            parse("demo 60"),
+            Ok(Command::Action(Action::SetTimeScale(s))) if s == 60.0
+        ));
+        for line in ["demo 0", "demo -1", "demo nan", "demo inf", "demo 5000", "demo fast"] {
+            assert!(parse(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse("sync"), Ok(Command::Sync)));
+        assert!(matches!(parse("  help  "), Ok(Command::Help)));
+        assert!(matches!(parse("bright 50"), Ok(Command::Brightness(Some(50)))));
+        assert!(matches!(parse("bright auto"), Ok(Command::Brightness(None))));
+        assert!(matches!(
+            parse("status"),
+            Ok(Command::Action(Action::ToggleStatusPage))
+        ));
+        assert!(matches!(
+            parse("screen off"),
+            Ok(Command::Action(Action::ScreenPower(false)))
+        ));
+        assert!(matches!(
+            parse("preset 90"),
+            Ok(Command::Action(Action::UpdateSettings(Settings {
+                countdown_preset: 90,
+                ..
+            })))
+        ));
+        assert!(matches!(
+            parse("tz Europe/Berlin"),
+            Ok(Command::Action(Action::UpdateSettings(Settings {
+                timezone: chrono_tz::Europe::Berlin,
+                ..
+            })))
+        ));
+    }
+
+    #[test]
+    fn invalid_commands() {
+        for line in ["", "bright 101", "bright", "preset 300", "screen", "tz", "tz Mars/Olympus"] {
+            assert!(parse(line).is_err(), "{:?}", line);
+        }
+        assert_eq!(parse("reboot").unwrap_err(), HELP);
+    }
+}
@@ -2,31 +2,41 @@
 // SPDX-License-Identifier: MIT
 
 use core::{
-    cell::RefCell,
-    fmt::{Debug, Display},
+    cell::Cell,
+    fmt::Display,
+    mem::Discriminant,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-use alloc::{boxed::Box, format, rc::Rc, vec::Vec};
-use chrono::{DateTime, Timelike, Utc};
-use chrono_tz::{Europe::Paris, Tz};
+use alloc::{format, rc::Rc, vec::Vec};
+use chrono::{DateTime, Datelike, TimeDelta, Timelike, Utc};
+use chrono_tz::Tz;
 use embassy_sync::{
     blocking_mutex::raw::CriticalSectionRawMutex,
     blocking_mutex::{CriticalSectionMutex, Mutex},
-    channel::Channel,
+    channel::{Channel, TrySendError},
     signal::Signal,
     waitqueue::WakerRegistration,
 };
+use embassy_futures::select::{select, Either};
+use embassy_net::Ipv4Address;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::prelude::Point;
 use i_slint_core::graphics::LinearGradientBrush;
 use log::{debug, error};
-use slint::{Brush, ComponentHandle, Image, Rgba8Pixel, SharedPixelBuffer, ToSharedString};
+use micromath::F32Ext;
+use mipidsi::options::Rotation;
+use slint::{
+    Brush, ComponentHandle, Image, Rgba8Pixel, SharedPixelBuffer, SharedString, ToSharedString,
+};
 use slint_generated::{Globals, MonsterEnv, Recipe, TimeOfDay, WifiState};
 
 use log::warn;
-use tiny_skia::{Color, FillRule, Mask, Paint, PathBuilder, Pixmap, Transform};
+use tiny_skia::Color;
 
 use crate::moon::Moon;
+use crate::settings::{self, Settings};
+use crate::sky::{NightSky, SkyColors, SkyGradientStyle, SKY_GRADIENT_STYLE, SKY_THEMES};
 
 #[cfg(feature = "mcu")]
 use crate::board::Board;
@@ -40,36 +50,339 @@ pub enum Action {
     TimeOfDayUpdate(TimeOfDay, Moon),
     UpdateTime(DateTime<Tz>),
     ShowMonster(bool),
+    ShowColon(bool),
     StartCountDown(DateTime<Tz>, u8),
+    NextSkyTheme,
+    /// Encoder detents, see `ToggleTimeAdjust` for what they change.
+    Rotate(i8),
+    /// Switches `Rotate` between the countdown and setting the clock.
+    ToggleTimeAdjust,
+    DismissCountDown,
+    /// Silences the expired countdown and rings it again after that many minutes.
+    SnoozeAlarm(u16),
+    UpdateSettings(Settings),
+    SystemStatus(SystemStatus),
+    ToggleStatusPage,
+    SetRotation(Rotation),
+    FrameStats(FrameStats),
+    ToggleFrameStats,
+    TemperatureUpdate(f32),
+    ScreenPower(bool),
+    SetTimeScale(f32),
+    SetAccessibilityMode(bool),
+    /// Current conditions, `None` when no forecast is available.
+    WeatherUpdate(Option<Weather>),
+    /// Drops the cached moon so the next `UpdateTime` redraws it.
+    RefreshMoon,
+    /// The free heap went below `LOW_MEMORY_THRESHOLD` or kept falling (true), or recovered
+    /// (false), see `HeapTrend`.
+    LowMemoryWarning(bool),
+    /// Switches the night sky between the dark gradients and the moonlit one, see `NightSky`.
+    SetNightSky(NightSky),
+    /// Address given by DHCP, for the status page. Cleared by a `WifiStateUpdate` other than OK.
+    IpAssigned(Ipv4Address),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weather {
+    CLEAR,
+    CLOUDY,
+    RAIN,
+    SNOW,
+}
+
+impl Weather {
+    /// The monster doesn't go out in the rain nor the snow.
+    fn keeps_monster_home(&self) -> bool {
+        matches!(self, Weather::RAIN | Weather::SNOW)
+    }
+}
+
+/// Render loop performance, for the on-screen overlay.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    /// Moving average of the time to draw a frame.
+    pub frame_time_ms: f32,
+    /// Frames actually drawn during the last second.
+    pub fps: f32,
+    /// Actions waiting in `ACTION` when the stats were sampled, out of `ACTION_CHANNEL_DEPTH`.
+    pub queued_actions: usize,
+}
+
+/// Health of the subsystems, for the status page.
+#[derive(Debug, Clone)]
+pub struct SystemStatus {
+    pub wifi: WifiState,
+    /// Seconds since the last successful NTP sync, `None` if it never happened.
+    pub ntp_synced_secs_ago: Option<u64>,
+    /// False when the RTC oscillator stopped (e.g. lost battery), so its time can't be trusted.
+    pub rtc_trusted: bool,
+    /// Name of the clock the time is read from.
+    pub time_source: &'static str,
+    pub heap_free: usize,
+    pub cpu_mhz: u32,
 }
 
+/// Number of actions that can wait for the controller before `ACTION_BACKPRESSURE` kicks in.
 #[cfg(feature = "mcu")]
-type ActionChannelType =
-    Channel<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, Action, 4>;
+pub const ACTION_CHANNEL_DEPTH: usize = 4;
+/// The simulator queue is kept small on purpose, to surface backpressure issues early. It
+/// still holds the `MultipleActions` of `update_timer` plus one user action, and a newer burst
+/// coalesces with a queued one. The cfgs are exclusive so that enabling both features (the
+/// default `mcu` is easy to forget) doesn't define the constant twice.
+#[cfg(not(feature = "mcu"))]
+pub const ACTION_CHANNEL_DEPTH: usize = 2;
 
-#[cfg(feature = "simulator")]
-type ActionChannelType =
-    Channel<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, Action, 2>;
+type ActionChannelType = Channel<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    Action,
+    ACTION_CHANNEL_DEPTH,
+>;
 
-type RefreshScreenChannelType =
-    Channel<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, Action, 1>;
+/// What `send_action` does when the action queue is full.
+#[derive(Debug, Clone, Copy)]
+pub enum Backpressure {
+    /// Keep the queue as is and drop the action being sent.
+    DropNewest,
+    /// Drop the action that waited the longest to make room for the new one.
+    DropOldest,
+    /// Drop a queued action of the same kind (e.g. a stale `UpdateTime`) and queue the new one
+    /// last, falling back to `DropOldest` when there is none.
+    Coalesce,
+}
+
+pub const ACTION_BACKPRESSURE: Backpressure = Backpressure::Coalesce;
+
+/// Wakes the render loop up so it redraws the window.
+/// Requests are coalesced: several `request()` before a `wait()` end up in a single redraw.
+pub struct RedrawRequest {
+    signal: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl RedrawRequest {
+    pub const fn new() -> Self {
+        Self {
+            signal: Signal::new(),
+        }
+    }
+
+    /// Asks the render loop to redraw, never blocks.
+    pub fn request(&self) {
+        self.signal.signal(());
+    }
+
+    /// Waits until a redraw is requested, consuming the request.
+    pub async fn wait(&self) {
+        self.signal.wait().await
+    }
+
+    /// Drops a pending request, if any.
+    pub fn clear(&self) {
+        self.signal.reset();
+    }
+
+    /// Whether a redraw was requested and the render loop didn't pick it up yet.
+    pub fn is_pending(&self) -> bool {
+        self.signal.signaled()
+    }
+}
+
+/// Display orientation asked by `Action::SetRotation`, applied by the render loop which owns
+/// the display.
+pub static ROTATION_REQUEST: Signal<CriticalSectionRawMutex, Rotation> = Signal::new();
+
+/// Rotation as stored in the settings, in quarter turns.
+pub fn rotation_from_quarters(quarters: u8) -> Rotation {
+    match quarters % 4 {
+        0 => Rotation::Deg0,
+        1 => Rotation::Deg90,
+        2 => Rotation::Deg180,
+        _ => Rotation::Deg270,
+    }
+}
+
+pub fn rotation_to_quarters(rotation: Rotation) -> u8 {
+    match rotation {
+        Rotation::Deg0 => 0,
+        Rotation::Deg90 => 1,
+        Rotation::Deg180 => 2,
+        Rotation::Deg270 => 3,
+    }
+}
+
+/// The rotation a quarter turn clockwise from the current one, to cycle through mountings.
+pub fn next_rotation() -> Rotation {
+    rotation_from_quarters(settings::current().rotation + 1)
+}
+
+/// Signaled each time the sky is recomputed, for ambient lights behind the clock to follow it.
+pub static SKY_COLORS: Signal<CriticalSectionRawMutex, SkyColors> = Signal::new();
+
+static NIGHT_FACTOR: CriticalSectionMutex<Cell<Option<f32>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+/// Darkness of the sky as last computed by the controller, from 0.0 (day) to 1.0 (night).
+/// `None` until the first `UpdateTime`.
+pub fn night_factor() -> Option<f32> {
+    NIGHT_FACTOR.lock(|n| n.get())
+}
+
+/// Make the monster livelier during the day and calmer at night, set to false for a
+/// constant pace.
+pub const MONSTER_SPEED_BY_TIME_OF_DAY: bool = true;
+
+static MONSTER_SPEED: CriticalSectionMutex<Cell<f32>> = CriticalSectionMutex::new(Cell::new(1.0));
+
+/// Monster animation speed factor, 1.0 being the nominal pace.
+pub fn monster_speed() -> f32 {
+    MONSTER_SPEED.lock(|m| m.get())
+}
+
+/// Seconds between two monster blinks, following `monster_speed`.
+pub fn monster_blink_period_secs() -> u32 {
+    ((10.0 / monster_speed()) as u32).max(1)
+}
+
+fn monster_speed_for(tod: TimeOfDay) -> f32 {
+    if !MONSTER_SPEED_BY_TIME_OF_DAY {
+        return 1.0;
+    }
+    match tod {
+        TimeOfDay::DAY => 1.25,
+        TimeOfDay::TWILIGHT => 1.0,
+        TimeOfDay::NIGHT => 0.75,
+    }
+}
+
+static SCREEN_ON: AtomicBool = AtomicBool::new(true);
+
+/// Signaled on every `Action::ScreenPower`, for the backlight task to react right away.
+pub static SCREEN_POWER_CHANGED: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// False while the screen is turned off by `Action::ScreenPower(false)`.
+pub fn screen_is_on() -> bool {
+    SCREEN_ON.load(Ordering::Relaxed)
+}
+
+static FRAME_STATS_VISIBLE: AtomicBool = AtomicBool::new(false);
+
+/// Whether the frame stats overlay is shown, the render loop only reports them then.
+pub fn frame_stats_visible() -> bool {
+    FRAME_STATS_VISIBLE.load(Ordering::Relaxed)
+}
+
+/// Time scale of the "demo day" mode, a whole day goes by in 5 minutes.
+pub const DEMO_TIME_SCALE: f32 = 288.0;
+/// Fastest time scale, an hour per second. Beyond it the scaled clock would leave chrono's
+/// range within a run.
+pub const MAX_TIME_SCALE: f32 = 3600.0;
+
+/// Whether `Action::SetTimeScale` accepts `scale`: finite, positive and at most
+/// `MAX_TIME_SCALE`.
+pub fn is_valid_time_scale(scale: f32) -> bool {
+    scale.is_finite() && scale > 0.0 && scale <= MAX_TIME_SCALE
+}
+
+#[derive(Clone, Copy)]
+struct TimeScale {
+    scale: f32,
+    /// Real and scaled time the scaled clock started from, `None` at scale 1.0.
+    anchor: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    /// Last real and scaled time handed out, a new scale carries on from there.
+    last: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+static TIME_SCALE: CriticalSectionMutex<Cell<TimeScale>> =
+    CriticalSectionMutex::new(Cell::new(TimeScale {
+        scale: 1.0,
+        anchor: None,
+        last: None,
+    }));
+
+/// Speed of the clock set by `Action::SetTimeScale`, 1.0 being real time.
+pub fn time_scale() -> f32 {
+    TIME_SCALE.lock(|t| t.get().scale)
+}
+
+/// Time to display for the real time `now`, running `time_scale()` times faster than real time.
+/// Back at 1.0 the clock jumps back to the real time.
+pub fn scaled_time(now: DateTime<Utc>) -> DateTime<Utc> {
+    TIME_SCALE.lock(|t| {
+        let mut time_scale = t.get();
+        let scaled = if time_scale.scale == 1.0 {
+            time_scale.anchor = None;
+            now
+        } else {
+            let (real, start) = *time_scale.anchor.get_or_insert((now, now));
+            let elapsed_ms = (now - real).num_milliseconds() as f64 * time_scale.scale as f64;
+            // out of chrono's range only after years at full speed, carry on from real time
+            chrono::TimeDelta::try_milliseconds(elapsed_ms as i64)
+                .and_then(|elapsed| start.checked_add_signed(elapsed))
+                .unwrap_or(now)
+        };
+        time_scale.last = Some((now, scaled));
+        t.set(time_scale);
+        scaled
+    })
+}
+
+fn set_time_scale(scale: f32) {
+    TIME_SCALE.lock(|t| {
+        let mut time_scale = t.get();
+        time_scale.scale = scale;
+        time_scale.anchor = time_scale.last;
+        t.set(time_scale);
+    })
+}
 
 pub static ACTION: ActionChannelType = Channel::new();
-pub static REFRESH_SIGNAL: RefreshScreenChannelType = Channel::new();
+pub static REDRAW: RedrawRequest = RedrawRequest::new();
 pub static WAKER: WakerRegistration = WakerRegistration::new();
 static SOME_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
-struct MoonAndTime {
-    version: DateTime<Tz>,
+/// Marks when a value shown in `Globals` was last computed, in clock time, so it is only
+/// recomputed once it gets `max_age_secs` old. The value itself lives in the UI.
+pub struct CachedValue {
+    max_age_secs: i64,
+    computed_for: CriticalSectionMutex<Cell<Option<DateTime<Tz>>>>,
 }
-impl Debug for MoonAndTime {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "MoonAndTime(version: {})", self.version)
+
+impl CachedValue {
+    pub const fn new(max_age_secs: i64) -> Self {
+        Self {
+            max_age_secs,
+            computed_for: CriticalSectionMutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Returns true when the value was never computed or is more than `max_age_secs` away
+    /// from `now`, marking it as computed for `now` at the same time. Checking and marking
+    /// under a single lock makes sure a burst of updates only recomputes once.
+    pub fn claim(&self, now: DateTime<Tz>) -> bool {
+        self.computed_for.lock(|c| {
+            let fresh = c
+                .get()
+                .is_some_and(|at| (now - at).num_seconds().abs() < self.max_age_secs);
+            if !fresh {
+                c.set(Some(now));
+            }
+            !fresh
+        })
+    }
+
+    /// Forces the next `claim` to recompute.
+    pub fn invalidate(&self) {
+        self.computed_for.lock(|c| c.set(None));
     }
 }
 
-static CURRENT_MOON: CriticalSectionMutex<RefCell<Option<MoonAndTime>>> =
-    CriticalSectionMutex::new(RefCell::new(Option::None));
+/// How long the sky computed for a time is reused, in seconds of clock time.
+pub const SKY_RECOMPUTE_SECS: i64 = 60;
+/// How long the moon image computed for a time is reused, in seconds of clock time. The
+/// crescent barely changes within hours, `Action::RefreshMoon` redraws it right away.
+pub const MOON_RECOMPUTE_SECS: i64 = 6 * 3600;
+
+static CURRENT_MOON: CachedValue = CachedValue::new(MOON_RECOMPUTE_SECS);
 
 pub trait WallClock {
     async fn get_date_time(&self) -> DateTime<Utc>;
@@ -85,13 +398,217 @@ pub trait Hardware {
 #[cfg(feature = "mcu")]
 impl Hardware for Board {}
 
-pub const MOON_SIZE: usize = 34;
+/// Side of the moon image, in pixels. The Slint moon box stays 34px, a larger moon is centered
+/// on it.
+pub const MOON_SIZE: u32 = crate::moon::DEFAULT_SIZE;
+/// Color of the lit part of the moon, e.g. (255, 255, 255) for a white moon.
+pub const MOON_COLOR: (u8, u8, u8) = crate::moon::DEFAULT_COLOR;
+
+/// Seconds added or removed from the countdown for each encoder detent.
+pub const COUNTDOWN_STEP_SECS: i64 = 10;
+/// Seconds added or removed from the clock for each encoder detent, while adjusting the time.
+pub const TIME_ADJUST_STEP_SECS: i64 = 60;
+
+/// Countdown duration, in seconds, used by the next `StartCountDown` from the buttons.
+/// Adjusted with `Action::Rotate` while no countdown is running.
+pub fn countdown_preset() -> u8 {
+    settings::current().countdown_preset
+}
+
+/// Once a countdown expires, keep it on screen counting up in red ("-01:23") until it is
+/// dismissed, instead of hiding it after a few seconds.
+pub const COUNTDOWN_OVERRUN: bool = true;
+
+/// Minutes a snoozed countdown waits before ringing again.
+pub const SNOOZE_MINUTES: u16 = 5;
+
+/// Light the backlight up progressively before the countdown rings, like a sunrise.
+pub const SUNRISE_ALARM: bool = true;
+
+/// How long before the countdown rings the sunrise ramp starts, in seconds.
+pub const SUNRISE_RAMP_SECS: i64 = 10 * 60;
+
+static SUNRISE_RAMP: CriticalSectionMutex<Cell<Option<f32>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+/// Progress of the sunrise ramp, from 0.0 when it starts to 1.0 when the countdown rings.
+/// `None` outside of the ramp window. Read by the backlight task, and by external LEDs.
+pub fn sunrise_ramp() -> Option<f32> {
+    SUNRISE_RAMP.lock(|r| r.get())
+}
+
+static ALARM_RINGING: AtomicBool = AtomicBool::new(false);
+
+/// True while an expired countdown is over-running, the buttons snooze it instead of starting
+/// a new one.
+pub fn alarm_ringing() -> bool {
+    ALARM_RINGING.load(Ordering::Relaxed)
+}
+
+/// Darken the sky while the monster is indoors (`MonsterEnv::HOUSE` or `SLEEPING`), as seen
+/// through a window, instead of showing it fully.
+pub const DIM_SKY_INDOORS: bool = false;
+/// How much darker the sky gets indoors at full night, see `slint::Brush::darker`. It scales
+/// with the night factor, a daylight sky stays as is.
+const INDOOR_SKY_DARKENING: f32 = 0.6;
+
+/// Night sky at boot, `Action::SetNightSky` switches it.
+pub const NIGHT_SKY: NightSky = NightSky::DARK;
+
+/// Draw the day arc of the sun with the sun on it, over the sky.
+pub const SUN_ARC: bool = true;
+
+/// Most actions processed out of a single `MultipleActions`, once flattened. Each one costs a
+/// tick of the controller loop, the rest is dropped. Tune it if batches legitimately grow.
+pub const MAX_BATCH_ACTIONS: usize = 16;
+
+/// Free heap, in bytes, under which `Action::LowMemoryWarning` fires. The moon and sky images
+/// need a few KB each to be redrawn.
+pub const LOW_MEMORY_THRESHOLD: usize = 16 * 1024;
+/// Free heap samples `HeapTrend` looks back at.
+pub const HEAP_TREND_SAMPLES: usize = 8;
+/// Loss of free heap, in bytes, over `HEAP_TREND_SAMPLES` samples all lower or equal to the
+/// previous one, that counts as a leak rather than noise.
+pub const HEAP_TREND_MIN_DROP: usize = 4 * 1024;
+
+/// Watches free heap samples, taken at a steady rate, for an upcoming allocation failure.
+pub struct HeapTrend {
+    samples: [usize; HEAP_TREND_SAMPLES],
+    len: usize,
+    low: bool,
+}
+
+impl HeapTrend {
+    pub const fn new() -> Self {
+        Self {
+            samples: [0; HEAP_TREND_SAMPLES],
+            len: 0,
+            low: false,
+        }
+    }
+
+    /// Records a sample, returns the `Action::LowMemoryWarning` to send when the state changes.
+    pub fn push(&mut self, heap_free: usize) -> Option<Action> {
+        if self.len == HEAP_TREND_SAMPLES {
+            self.samples.rotate_left(1);
+            self.len -= 1;
+        }
+        self.samples[self.len] = heap_free;
+        self.len += 1;
+
+        let samples = &self.samples[..self.len];
+        let falling = self.len == HEAP_TREND_SAMPLES
+            && samples.windows(2).all(|w| w[1] <= w[0])
+            && samples[0] - heap_free >= HEAP_TREND_MIN_DROP;
+        let low = heap_free < LOW_MEMORY_THRESHOLD || falling;
+        if low == self.low {
+            return None;
+        }
+        self.low = low;
+        Some(Action::LowMemoryWarning(low))
+    }
+}
+
+/// Composite the moon, with a halo, over the sky color in Rust (see `Moon::composite_on_sky`)
+/// instead of layering the bare moon image over the sky brush. The image is an opaque square
+/// of the upper sky color, larger than the moon, so it only blends in with a flat enough sky,
+/// and it is redrawn with each sky update instead of every few hours.
+pub const MOON_COMPOSITE: bool = false;
+/// Tilt the moon so its lit limb faces the sun as seen from `sky::location`
+/// (see `Moon::with_parallactic_rotation`). The fixed `moon::DEFAULT_ROTATION` is then only the
+/// fallback when the sun can't be placed.
+pub const MOON_PARALLACTIC_ROTATION: bool = true;
+/// The tilt changes through the night: a sky update redraws the moon once it turned by this
+/// many degrees since it was drawn, rather than with every sky update.
+const MOON_TILT_REDRAW_DEGREES: f32 = 5.0;
+/// Side of the composited moon image, in pixels, room for the halo around the moon.
+const MOON_COMPOSITE_SIZE: u32 = 2 * MOON_SIZE;
+
+/// Draw the time digits with tiny-skia (see the `digits` module) instead of the Slint font,
+/// smoother at large sizes but the image costs ~45KB of heap.
+pub const SKIA_DIGITS: bool = false;
+
+/// Height of the tiny-skia digits, in pixels.
+pub const DIGITS_SIZE: u32 = 64;
+
+/// Blink the colon of the time display once per second, set to false for a steady colon.
+pub const BLINK_COLON: bool = true;
+
+/// How long the sky theme name stays on screen after a switch, in seconds.
+const SKY_THEME_NAME_DISPLAY_SECS: i64 = 3;
+
+/// Dates greeted with a banner, as (month, day, message). The birthday from the settings is
+/// greeted too.
+pub const SPECIAL_DATES: &[(u32, u32, &str)] = &[
+    (1, 1, "happy new year!"),
+    (7, 14, "bonne fete nationale!"),
+    (10, 31, "happy halloween!"),
+    (12, 25, "merry christmas!"),
+];
+
+const BIRTHDAY_MESSAGE: &str = "happy birthday!";
+
+/// Banner shown on the given local date, if it is a special one.
+pub fn special_date_message(month: u32, day: u32) -> Option<&'static str> {
+    let is_birthday = settings::current()
+        .birthday
+        .is_some_and(|(m, d)| m as u32 == month && d as u32 == day);
+    if is_birthday {
+        return Some(BIRTHDAY_MESSAGE);
+    }
+    SPECIAL_DATES
+        .iter()
+        .find(|(m, d, _)| *m == month && *d == day)
+        .map(|(_, _, message)| *message)
+}
+
+/// Before that year the time is a leftover of the epoch, not a real one.
+pub const MIN_PLAUSIBLE_YEAR: i32 = 2025;
+
+/// Let the weather decide whether the monster stays home, set to false to only follow the sky.
+pub const WEATHER_MOODS: bool = true;
+
+/// A forecast older than that is ignored, the monster falls back on the sky alone.
+const WEATHER_MAX_AGE: Duration = Duration::from_secs(3 * 3600);
+
+/// Sensor updates of the same kind arriving within this window only repaint once, with the
+/// latest value.
+pub const SENSOR_COALESCING_WINDOW: Duration = Duration::from_millis(250);
+
+impl Action {
+    /// Frequent readings that don't need to be rendered as fast as they come.
+    fn is_sensor_update(&self) -> bool {
+        matches!(self, Action::TemperatureUpdate(_))
+    }
+}
+
+/// Last time a kind of sensor update reached the UI, and the value held back since then.
+struct CoalescedUpdate {
+    kind: Discriminant<Action>,
+    last_applied: Instant,
+    pending: Option<Action>,
+}
 
 pub struct Controller<'a, Hardware, WallClock> {
     main_window: &'a Recipe,
     hardware: Hardware,
     wall_clock: Rc<WallClock>,
-    current_sky: CriticalSectionMutex<RefCell<Option<MoonAndTime>>>,
+    current_sky: CachedValue,
+    sky_theme: usize,
+    night_sky: NightSky,
+    sensor_updates: Vec<CoalescedUpdate>,
+    /// Whether the RTC or NTP vouch for the time, as of the last `SystemStatus`.
+    time_source_trusted: bool,
+    /// Last `WeatherUpdate` and when it arrived.
+    weather: Option<(Weather, Instant)>,
+    /// Hour and minute of the tiny-skia digits shown, see `SKIA_DIGITS`.
+    digits_for: Option<(u32, u32)>,
+    /// Sky colors as last computed, behind the moon when `MOON_COMPOSITE` is set.
+    sky_colors: Option<SkyColors>,
+    /// Tilt of the moon shown, see `MOON_TILT_REDRAW_DEGREES`.
+    moon_rotation: Option<f32>,
+    /// Whether `Action::Rotate` sets the clock rather than the countdown.
+    time_adjust: bool,
 }
 
 impl<'a, H, WC> Controller<'a, H, WC>
@@ -104,7 +621,17 @@ where
             main_window,
             hardware,
             wall_clock,
-            current_sky: CriticalSectionMutex::new(RefCell::new(Option::None)),
+            current_sky: CachedValue::new(SKY_RECOMPUTE_SECS),
+            sky_theme: settings::current().sky_theme as usize % SKY_THEMES.len(),
+            night_sky: NIGHT_SKY,
+            sensor_updates: Vec::new(),
+            // until told otherwise, the simulator never reports a status
+            time_source_trusted: true,
+            weather: None,
+            digits_for: None,
+            sky_colors: None,
+            moon_rotation: None,
+            time_adjust: false,
         }
     }
 
@@ -112,7 +639,22 @@ where
         self.set_action_event_handlers();
 
         loop {
-            let action = ACTION.receive().await;
+            let next_pending = self
+                .sensor_updates
+                .iter()
+                .filter(|u| u.pending.is_some())
+                .map(|u| u.last_applied + SENSOR_COALESCING_WINDOW)
+                .min();
+            let action = match next_pending {
+                Some(deadline) => match select(ACTION.receive(), Timer::at(deadline)).await {
+                    Either::First(action) => action,
+                    Either::Second(_) => match self.take_due_sensor_update() {
+                        Some(action) => action,
+                        None => continue,
+                    },
+                },
+                None => ACTION.receive().await,
+            };
 
             match self.process_action(action).await {
                 Ok(()) => {
@@ -125,23 +667,71 @@ where
         }
     }
 
+    /// The held back update whose window ends first, the one the loop timer was set for.
+    fn take_due_sensor_update(&mut self) -> Option<Action> {
+        self.sensor_updates
+            .iter_mut()
+            .filter(|u| u.pending.is_some())
+            .min_by_key(|u| u.last_applied)
+            .and_then(|u| u.pending.take())
+    }
+
+    /// Returns false when the sensor update must be held back, see `SENSOR_COALESCING_WINDOW`.
+    fn coalesce_sensor_update(&mut self, action: &Action) -> bool {
+        let kind = core::mem::discriminant(action);
+        let now = Instant::now();
+        match self.sensor_updates.iter_mut().find(|u| u.kind == kind) {
+            Some(update) if now < update.last_applied + SENSOR_COALESCING_WINDOW => {
+                update.pending = Some(action.clone());
+                false
+            }
+            Some(update) => {
+                update.last_applied = now;
+                update.pending = None;
+                true
+            }
+            None => {
+                self.sensor_updates.push(CoalescedUpdate {
+                    kind,
+                    last_applied: now,
+                    pending: None,
+                });
+                true
+            }
+        }
+    }
+
     pub async fn process_action(&mut self, action: Action) -> Result<(), ()> {
+        let Action::MultipleActions(actions) = action else {
+            return self.process_single_action(action).await;
+        };
+        // nested batches are flattened and run in a single loop: no recursive boxed futures
+        // whatever the nesting, and the batch is capped to bound the time it takes
+        for a in bounded_batch(actions) {
+            let _ = self.process_single_action(a).await;
+        }
+        Ok(())
+    }
+
+    /// Handles anything but `MultipleActions`, see `process_action`.
+    async fn process_single_action(&mut self, action: Action) -> Result<(), ()> {
         let globals = self.main_window.global::<Globals>();
 
+        if action.is_sensor_update() && !self.coalesce_sensor_update(&action) {
+            log::debug!("coalescing: {:?}", action);
+            return Ok(());
+        }
+
         log::info!("process_action: {:?}", action);
 
         // Refresh has to be asked BEFORE updating
         // see https://github.com/slint-ui/slint/discussions/3994#discussioncomment-7680584
-        match REFRESH_SIGNAL.try_send(action.clone()) {
-            Ok(_) => {
-                log::info!(
-                    "{} - trigger refresh: {:?}",
-                    Instant::now().as_millis(),
-                    action
-                );
-            }
-            Err(_) => debug!("refresh action queue full, could not add: {:?}", action),
-        };
+        REDRAW.request();
+        log::info!(
+            "{} - trigger refresh: {:?}",
+            Instant::now().as_millis(),
+            action
+        );
         Timer::after(Duration::from_millis(1)).await;
         match action.clone() {
             Action::HardwareUserBtnPressed(is_pressed) => {
@@ -154,48 +744,148 @@ where
                     // self.hardware.green_led_set_high()
                 }
             }
+            Action::StartCountDown(current_time, _)
+                if COUNTDOWN_OVERRUN
+                    && globals.get_countdown() != 0
+                    && globals.get_countdown() <= current_time.timestamp() =>
+            {
+                // the button acknowledges an over-running countdown instead of restarting it
+                globals.set_countdown(0);
+                ALARM_RINGING.store(false, Ordering::Relaxed);
+            }
+            Action::DismissCountDown => {
+                globals.set_countdown(0);
+                ALARM_RINGING.store(false, Ordering::Relaxed);
+            }
             Action::StartCountDown(current_time, duration) => {
-                let d = chrono::Duration::seconds(duration.into());
-                let stops_at = current_time.checked_add_signed(d).unwrap();
+                // out of chrono's range only with a broken clock or a much longer duration, not
+                // worth a reboot: the countdown is refused and the current one kept
+                let Some(stops_at) = countdown_stops_at(current_time, duration) else {
+                    error!("countdown of {}s from {} overflows", duration, current_time);
+                    return Ok(());
+                };
                 globals.set_countdown(stops_at.timestamp());
                 globals.set_countdown_total_duration(duration.into());
+                ALARM_RINGING.store(false, Ordering::Relaxed);
+            }
+            Action::SnoozeAlarm(_) if !alarm_ringing() => {
+                log::info!("no alarm ringing, nothing to snooze");
+            }
+            Action::SnoozeAlarm(minutes) => {
+                let snooze_secs = minutes as i64 * 60;
+                globals.set_countdown(globals.get_current_time() + snooze_secs);
+                globals.set_countdown_total_duration(snooze_secs as i32);
+                ALARM_RINGING.store(false, Ordering::Relaxed);
+            }
+            Action::WifiStateUpdate(wifi_state) => {
+                if wifi_state != WifiState::OK {
+                    globals.set_ip_address(Default::default());
+                }
+                globals.set_wifi_state(wifi_state);
+            }
+            Action::SetNightSky(night_sky) => {
+                log::info!("night sky: {:?}", night_sky);
+                self.night_sky = night_sky;
+                self.current_sky.invalidate();
+            }
+            Action::IpAssigned(address) => {
+                globals.set_ip_address(format!("{}", address).to_shared_string());
             }
-            Action::WifiStateUpdate(wifi_state) => globals.set_wifi_state(wifi_state),
             Action::UpdateTime(current_time) => {
                 globals.set_current_time(current_time.timestamp());
+                let countdown = globals.get_countdown();
+                ALARM_RINGING.store(
+                    COUNTDOWN_OVERRUN && countdown != 0 && countdown <= current_time.timestamp(),
+                    Ordering::Relaxed,
+                );
+                let remaining = countdown - current_time.timestamp();
+                let ramp = (SUNRISE_ALARM && countdown != 0 && remaining > 0)
+                    .then_some(remaining)
+                    .filter(|&remaining| remaining <= SUNRISE_RAMP_SECS)
+                    .map(|remaining| 1.0 - remaining as f32 / SUNRISE_RAMP_SECS as f32);
+                SUNRISE_RAMP.lock(|r| r.set(ramp));
+                let time_set =
+                    self.time_source_trusted && current_time.year() >= MIN_PLAUSIBLE_YEAR;
+                globals.set_time_set(time_set);
+                // evaluated on every tick, so the banner goes away as the day changes
+                let special_date = special_date_message(current_time.month(), current_time.day())
+                    .filter(|_| time_set);
+                globals.set_special_date(special_date.unwrap_or_default().to_shared_string());
+                let hh_mm = (current_time.hour(), current_time.minute());
+                if SKIA_DIGITS && time_set && self.digits_for != Some(hh_mm) {
+                    self.digits_for = Some(hh_mm);
+                    let digits = crate::digits::render_time_digits(hh_mm.0, hh_mm.1, DIGITS_SIZE);
+                    globals.set_time_digits(Image::from_rgba8(digits));
+                }
+                if globals.get_status_page_visible() {
+                    // the instant as the wall clock gave it, before any time zone conversion
+                    let utc = current_time.naive_utc().format("%Y-%m-%d %H:%M:%S");
+                    globals.set_utc_time(format!("{}", utc).to_shared_string());
+                }
+                // embassy instants start at boot, a low uptime means the device rebooted
+                globals.set_uptime(format_duration(Instant::now().as_secs()).to_shared_string());
+                if settings::current().high_contrast {
+                    // no sky, moon nor monster on the high contrast layout
+                    return Ok(());
+                }
 
-                let up_to_date_sky = self.current_sky.lock(|r| {
-                    r.borrow()
-                        .as_ref()
-                        .is_some_and(|m| (current_time - m.version).num_seconds().abs() < 60)
-                });
-                if (!up_to_date_sky) {
-                    self.current_sky.lock(|r| {
-                        r.replace(Option::Some(MoonAndTime {
-                            version: current_time,
-                        }))
-                    });
-
-                    log::info!("Generating sky and position for 1m");
-                    let (tod, night_factor, brush) =
-                        crate::sky::get_slint_gradient(current_time.to_utc());
+                if self.current_sky.claim(current_time) {
+                    log::info!("Generating sky and position");
+                    #[cfg(test)]
+                    tests::SKY_RECOMPUTES.with(|n| n.set(n.get() + 1));
+                    let (tod, night_factor, brush, sun, colors) = crate::sky::get_slint_gradient(
+                        current_time.to_utc(),
+                        SKY_THEMES[self.sky_theme],
+                        self.night_sky,
+                    );
                     globals.set_night_factor(night_factor);
+                    NIGHT_FACTOR.lock(|n| n.set(Some(night_factor)));
                     globals.set_time_of_day(tod);
+                    let speed = monster_speed_for(tod);
+                    MONSTER_SPEED.lock(|m| m.set(speed));
+                    globals.set_monster_speed(speed);
 
-                    let mut point = Point { x: 125, y: 188 }; // outside
-                    let mut env = slint_generated::MonsterEnv::OUTSIDE;
+                    let weather = self
+                        .weather
+                        .filter(|(_, received)| received.elapsed() < WEATHER_MAX_AGE)
+                        .map(|(weather, _)| weather);
+                    let (env, point) = monster_env(night_factor, weather);
 
-                    let local_time = current_time.with_timezone(&Paris);
-                    if ((local_time.hour() >= 20 || local_time.hour() < 8) || night_factor > 0.25) {
-                        point = Point { x: 195, y: 138 }; // in house
-                        env = slint_generated::MonsterEnv::HOUSE;
+                    let brush = match env {
+                        MonsterEnv::HOUSE | MonsterEnv::SLEEPING if DIM_SKY_INDOORS => {
+                            // seen from inside, the darker the night the dimmer the window
+                            brush.darker(INDOOR_SKY_DARKENING * night_factor)
+                        }
+                        _ => brush,
+                    };
+                    globals.set_sky_brush(brush);
+                    SKY_COLORS.signal(colors);
+                    self.sky_colors = Some(colors);
+                    if MOON_COMPOSITE
+                        || moon_tilt_moved(self.moon_rotation, current_time.to_utc())
+                    {
+                        CURRENT_MOON.invalidate();
                     }
 
-                    if (local_time.hour() >= 21 || local_time.hour() < 7) {
-                        env = slint_generated::MonsterEnv::SLEEPING;
+                    match crate::sky::next_sun_event(current_time.to_utc()) {
+                        Some(event) => {
+                            globals.set_next_sun_event(event.at.timestamp());
+                            globals.set_next_sun_event_is_sunrise(event.sunrise);
+                        }
+                        None => globals.set_next_sun_event(0),
+                    }
+                    globals.set_sky_radial(SKY_GRADIENT_STYLE == SkyGradientStyle::RADIAL);
+                    globals.set_sun_position(slint_generated::SunPosition { x: sun.x, y: sun.y });
+                    match crate::sky::sun_arc_position(current_time.to_utc()) {
+                        Some(sun) if SUN_ARC => {
+                            globals.set_sun_arc_position(slint_generated::SunPosition {
+                                x: sun.x,
+                                y: sun.y,
+                            });
+                            globals.set_sun_arc_visible(true);
+                        }
+                        _ => globals.set_sun_arc_visible(false),
                     }
-
-                    globals.set_sky_brush(Brush::LinearGradient(brush));
                     globals.set_monster_position(slint_generated::MonsterPosition {
                         env: env,
                         x: point.x,
@@ -203,90 +893,194 @@ where
                     });
                 }
 
-                let updated_moon = CURRENT_MOON.lock(|r| {
-                    r.borrow()
-                        .as_ref()
-                        .is_some_and(|m| current_time.timestamp() - m.version.timestamp() < 3600)
-                });
-
-                if (!updated_moon) {
-                    CURRENT_MOON.lock(|r| {
-                        r.replace(Option::Some(MoonAndTime {
-                            version: current_time,
-                        }))
-                    });
-                    log::info!("Generating moon for 1h");
-                    let buff = Moon::new(current_time.to_utc()).build_image();
+                if CURRENT_MOON.claim(current_time) {
+                    log::info!("Generating moon");
+                    let moon = current_moon(current_time.to_utc());
+                    self.moon_rotation = Some(moon.rotation);
+                    globals.set_moon_zodiac_sign(moon.zodiac_sign().to_shared_string());
+                    globals.set_moon_cycle_fraction(moon.cycle_fraction());
+                    globals.set_moon_days_until_full(moon.days_until_full());
+                    globals.set_moon_days_until_new(moon.days_until_new());
+                    let buff = match self.sky_colors.filter(|_| MOON_COMPOSITE) {
+                        Some(colors) => {
+                            let sky = colors.end;
+                            let center = MOON_COMPOSITE_SIZE as f32 / 2.0;
+                            moon.composite_on_sky(
+                                Color::from_rgba8(sky.red(), sky.green(), sky.blue(), 255),
+                                MOON_COMPOSITE_SIZE,
+                                MOON_COMPOSITE_SIZE,
+                                center,
+                                center,
+                                MOON_SIZE,
+                            )
+                        }
+                        None => moon.build_image_sized(MOON_SIZE),
+                    };
                     globals.set_moon(Image::from_rgba8(buff));
                 }
             }
-            Action::ShowMonster(monster) => {
-                globals.set_monster_visibility(monster);
+            Action::ToggleTimeAdjust => {
+                self.time_adjust = !self.time_adjust;
+                log::info!("time adjust: {}", self.time_adjust);
+                globals.set_time_adjusting(self.time_adjust);
             }
-            Action::TimeOfDayUpdate(tod, moon) => {
-                globals.set_time_of_day(tod);
-
-                let mut full_moon_paint = Paint::default();
-                full_moon_paint.set_color_rgba8(255, 246, 153, 255);
-                full_moon_paint.anti_alias = true;
-
-                let mut pixmap = Pixmap::new(34, 34).unwrap();
-
-                let mut computed = (34.0 * (moon.illumination));
-                if (moon.phase > 0.5) {
-                    computed = computed + 34. / 2. as f32
+            Action::Rotate(delta) if self.time_adjust => {
+                let now = self.wall_clock.get_date_time().await;
+                let step = TimeDelta::seconds(delta as i64 * TIME_ADJUST_STEP_SECS);
+                // the seconds start over, as when setting a watch
+                let Some(time) = now.checked_add_signed(step).and_then(|t| t.with_second(0)) else {
+                    error!("time adjust of {} from {} overflows", step, now);
+                    return Ok(());
+                };
+                log::info!("time adjusted to {}", time);
+                self.wall_clock.set_date_time(time).await;
+            }
+            Action::Rotate(delta) => {
+                let step = delta as i64 * COUNTDOWN_STEP_SECS;
+                let now = globals.get_current_time();
+                let stops_at = globals.get_countdown();
+                if stops_at > now {
+                    // a countdown is running, move its end
+                    let stops_at = (stops_at + step).max(now);
+                    let total = (stops_at - now).max(globals.get_countdown_total_duration() as i64);
+                    globals.set_countdown(stops_at);
+                    globals.set_countdown_total_duration(total as i32);
                 } else {
-                    computed = 34. / 2. - computed as f32
+                    let preset = (countdown_preset() as i64 + step)
+                        .clamp(COUNTDOWN_STEP_SECS, u8::MAX as i64);
+                    log::info!("countdown preset: {}s", preset);
+                    settings::update(Settings {
+                        countdown_preset: preset as u8,
+                        ..settings::current()
+                    });
                 }
-                let shadow =
-                    PathBuilder::from_circle(computed, (34.0 / 2.0) as f32, (34 / 2) as f32)
-                        .unwrap();
-
-                log::info!(
-                    "phase: {}, computed: {}, emoji: {}",
-                    moon.phase,
-                    computed,
-                    moon.phase_emoji()
+            }
+            Action::UpdateSettings(new_settings) => {
+                self.sky_theme = new_settings.sky_theme as usize % SKY_THEMES.len();
+                self.current_sky.invalidate();
+                globals.set_fahrenheit(new_settings.fahrenheit);
+                settings::update(new_settings);
+            }
+            Action::SystemStatus(status) => {
+                // applied on the next UpdateTime
+                self.time_source_trusted =
+                    status.rtc_trusted || status.ntp_synced_secs_ago.is_some();
+                globals.set_system_status(slint_generated::SystemStatus {
+                    wifi: status.wifi,
+                    ntp: match status.ntp_synced_secs_ago {
+                        Some(secs) => format!("{} ago", format_duration(secs)).to_shared_string(),
+                        None => "never".to_shared_string(),
+                    },
+                    rtc_trusted: status.rtc_trusted,
+                    time_source: status.time_source.to_shared_string(),
+                    heap_free: status.heap_free as i32,
+                    cpu_mhz: status.cpu_mhz as i32,
+                });
+            }
+            Action::SetRotation(rotation) => {
+                log::info!("rotating display to {:?}", rotation);
+                ROTATION_REQUEST.signal(rotation);
+                settings::update(Settings {
+                    rotation: rotation_to_quarters(rotation),
+                    ..settings::current()
+                });
+            }
+            Action::ScreenPower(on) => {
+                log::info!("screen power: {}", on);
+                SCREEN_ON.store(on, Ordering::Relaxed);
+                SCREEN_POWER_CHANGED.signal(on);
+            }
+            Action::TemperatureUpdate(celsius) => {
+                // jitter under what is displayed would only cost repaints
+                let fahrenheit = settings::current().fahrenheit;
+                if displayed_temperature(celsius, fahrenheit)
+                    != displayed_temperature(globals.get_temperature(), fahrenheit)
+                {
+                    globals.set_temperature(celsius);
+                }
+            }
+            Action::LowMemoryWarning(low) => {
+                globals.set_low_memory(low);
+                if low {
+                    warn!("low on heap, dropping the moon image");
+                    // the moon is redrawn by the next UpdateTime, in a single fresh allocation
+                    globals.set_moon(Image::default());
+                    CURRENT_MOON.invalidate();
+                }
+            }
+            Action::SetTimeScale(scale) if !is_valid_time_scale(scale) => {
+                warn!(
+                    "ignoring time scale {}, it must be positive and at most {}",
+                    scale, MAX_TIME_SCALE
                 );
+            }
+            Action::SetTimeScale(scale) => {
+                log::info!("time scale: {}", scale);
+                set_time_scale(scale);
+            }
+            Action::FrameStats(stats) => {
+                globals.set_frame_time_ms(stats.frame_time_ms);
+                globals.set_fps(stats.fps);
+                globals.set_queued_actions(stats.queued_actions as i32);
+            }
+            Action::ToggleFrameStats => {
+                let visible = !frame_stats_visible();
+                FRAME_STATS_VISIBLE.store(visible, Ordering::Relaxed);
+                globals.set_frame_stats_visible(visible);
+            }
+            Action::ToggleStatusPage => {
+                globals.set_status_page_visible(!globals.get_status_page_visible());
+            }
+            Action::ShowColon(visible) => {
+                globals.set_colon_visible(visible);
+            }
+            Action::NextSkyTheme => {
+                self.sky_theme = (self.sky_theme + 1) % SKY_THEMES.len();
+                let theme = SKY_THEMES[self.sky_theme];
+                log::info!("Switching sky theme to {}", theme);
 
-                let full_moon = PathBuilder::from_circle(
-                    (34.0 / 2.0) as f32,
-                    (34.0 / 2.0) as f32,
-                    (34 / 2) as f32,
-                )
-                .unwrap();
-
-                let mut mask = Mask::new(34, 34).unwrap();
-                mask.fill_path(
-                    &shadow,
-                    FillRule::Winding,
-                    true,
-                    Transform::from_rotate_at(-25.0, 34. / 2., 34. / 2.),
-                );
-                mask.invert();
-
-                // let t = Transform::from_rotate(-20.0);
-                // pixmap.fill(Color::from_rgba8(2, 4, 38, 255));
-                pixmap.fill_path(
-                    &full_moon,
-                    &full_moon_paint,
-                    FillRule::Winding,
-                    Transform::identity(),
-                    Some(&mask),
-                );
+                settings::update(Settings {
+                    sky_theme: self.sky_theme as u8,
+                    ..settings::current()
+                });
 
-                let i = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(
-                    pixmap.data_mut(),
-                    MOON_SIZE.try_into().unwrap(),
-                    MOON_SIZE.try_into().unwrap(),
+                // drop the cached sky so the next UpdateTime recomputes it
+                self.current_sky.invalidate();
+                globals.set_sky_theme_name(theme.name().to_shared_string());
+                globals.set_sky_theme_name_until(
+                    globals.get_current_time() + SKY_THEME_NAME_DISPLAY_SECS,
                 );
-                globals.set_moon(Image::from_rgba8(i));
             }
-            Action::MultipleActions(actions) => {
-                for a in actions.iter() {
-                    let _ = Box::pin(self.process_action(a.clone())).await;
-                }
+            Action::SetAccessibilityMode(high_contrast) => {
+                log::info!("high contrast: {}", high_contrast);
+                globals.set_high_contrast(high_contrast);
+                // the sky and moon were left as is meanwhile, recompute them on the next tick
+                self.current_sky.invalidate();
+                CURRENT_MOON.invalidate();
+                settings::update(Settings {
+                    high_contrast,
+                    ..settings::current()
+                });
+            }
+            Action::WeatherUpdate(weather) => {
+                self.weather = weather
+                    .filter(|_| WEATHER_MOODS)
+                    .map(|weather| (weather, Instant::now()));
+                // move the monster on the next tick
+                self.current_sky.invalidate();
             }
+            Action::ShowMonster(monster) => {
+                globals.set_monster_visibility(monster);
+            }
+            Action::TimeOfDayUpdate(tod, moon) => {
+                globals.set_time_of_day(tod);
+                globals.set_moon(Image::from_rgba8(moon_image(moon)));
+            }
+            Action::RefreshMoon => {
+                log::info!("moon refresh requested");
+                CURRENT_MOON.invalidate();
+            }
+            Action::MultipleActions(_) => unreachable!("batches are flattened by process_action"),
         }
 
         Ok(())
@@ -299,23 +1093,172 @@ where
             let now = chrono::DateTime::from_timestamp(now, 0).unwrap();
             let stops_at = chrono::DateTime::from_timestamp(stops, 0).unwrap();
             let duration = stops_at - now;
-            format!(
-                "{:02}:{:02}",
-                duration.num_seconds() / 60,
-                duration.num_seconds() % 60
-            )
-            .to_shared_string()
+            let sign = if duration.num_seconds() < 0 { "-" } else { "" };
+            let seconds = duration.num_seconds().abs();
+            format!("{}{:02}:{:02}", sign, seconds / 60, seconds % 60).to_shared_string()
+        });
+
+        globals.on_format_sun_event(|now, at, sunrise| {
+            let minutes = (at - now).max(0) / 60;
+            let event = if sunrise { "sunrise" } else { "sunset" };
+            if minutes < 60 {
+                format!("{} in {}m", event, minutes).to_shared_string()
+            } else {
+                format!("{} in {}h{:02}m", event, minutes / 60, minutes % 60).to_shared_string()
+            }
+        });
+
+        globals.on_format_temperature(|celsius, fahrenheit| {
+            if fahrenheit {
+                format!("{:.1}F", celsius_to_fahrenheit(celsius)).to_shared_string()
+            } else {
+                format!("{:.1}C", celsius).to_shared_string()
+            }
         });
 
-        globals.on_format_time(|now| {
+        globals.on_format_time(|now, colon_visible| {
             let datetime = chrono::DateTime::from_timestamp(now, 0).unwrap();
-            datetime
-                .with_timezone(&Paris)
-                .format("%H:%M")
-                .to_shared_string()
+            clock_text(datetime, colon_visible, &settings::current())
         });
         globals.set_countdown(0);
+        globals.set_countdown_overrun(COUNTDOWN_OVERRUN);
+        globals.set_skia_digits(SKIA_DIGITS);
+        globals.set_action_queue_depth(ACTION_CHANNEL_DEPTH as i32);
+        globals.set_sun_arc_horizon(crate::sky::SUN_ARC_HORIZON);
+        globals.set_sun_arc_radius(crate::sky::SUN_ARC_RADIUS);
+        globals.set_high_contrast(settings::current().high_contrast);
+        globals.set_fahrenheit(settings::current().fahrenheit);
+    }
+}
+
+/// The moon `UpdateTime` shows at `time`, in `MOON_COLOR` and turned as set by
+/// `MOON_PARALLACTIC_ROTATION`.
+fn current_moon(time: DateTime<Utc>) -> Moon {
+    turned(Moon::new(time).with_color(MOON_COLOR), time)
+}
+
+/// `moon` as seen at `time`, see `MOON_PARALLACTIC_ROTATION`.
+fn turned(moon: Moon, time: DateTime<Utc>) -> Moon {
+    let (latitude, longitude) = crate::sky::location();
+    if MOON_PARALLACTIC_ROTATION {
+        moon.with_parallactic_rotation(time, latitude as f32, longitude as f32)
+    } else {
+        moon.seen_from_latitude(latitude as f32)
+    }
+}
+
+/// Whether the tilt of the moon at `time` is `MOON_TILT_REDRAW_DEGREES` away from the
+/// `shown` one.
+fn moon_tilt_moved(shown: Option<f32>, time: DateTime<Utc>) -> bool {
+    if !MOON_PARALLACTIC_ROTATION {
+        return false;
+    }
+    let Some(shown) = shown else {
+        return true;
+    };
+    let turn = (current_moon(time).rotation - shown).abs() % 360.0;
+    turn.min(360.0 - turn) >= MOON_TILT_REDRAW_DEGREES
+}
+
+/// Image of a moon given by `TimeOfDayUpdate`, drawn like the `UpdateTime` one at the time it
+/// was computed for.
+fn moon_image(moon: Moon) -> SharedPixelBuffer<Rgba8Pixel> {
+    let time = DateTime::from_timestamp(moon.timestamp, 0).unwrap_or_default();
+    turned(moon.with_color(MOON_COLOR), time).build_image_sized(MOON_SIZE)
+}
+
+/// Above this night factor the monster goes back home.
+pub const HOUSE_NIGHT_FACTOR: f32 = 0.25;
+/// From this night factor on the monster sleeps, 1.0 being a fully dark sky.
+pub const SLEEPING_NIGHT_FACTOR: f32 = 1.0;
+
+/// Where the monster is and what it does given how dark the sky is. Following the sky rather
+/// than the hour keeps it consistent across seasons. Bad weather keeps it home during the day,
+/// without a forecast only the sky counts.
+pub fn monster_env(night_factor: f32, weather: Option<Weather>) -> (MonsterEnv, Point) {
+    let bad_weather = weather.is_some_and(|w| w.keeps_monster_home());
+    if night_factor >= SLEEPING_NIGHT_FACTOR {
+        (MonsterEnv::SLEEPING, Point { x: 195, y: 138 })
+    } else if night_factor > HOUSE_NIGHT_FACTOR || bad_weather {
+        (MonsterEnv::HOUSE, Point { x: 195, y: 138 }) // in house
+    } else {
+        (MonsterEnv::OUTSIDE, Point { x: 125, y: 188 }) // outside
+    }
+}
+
+/// The time as the clock face shows it, in the timezone and on the dial of `settings`.
+fn clock_text(time: DateTime<Utc>, colon_visible: bool, settings: &Settings) -> SharedString {
+    // keep the glyph slot so the digits don't shift while blinking
+    let format = match (settings.hour12, colon_visible) {
+        (false, true) => "%H:%M",
+        (false, false) => "%H %M",
+        (true, true) => "%I:%M",
+        (true, false) => "%I %M",
+    };
+    time.with_timezone(&settings.timezone)
+        .format(format)
+        .to_shared_string()
+}
+
+pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+/// Smallest temperature change shown, matching the one decimal of `format_temperature`.
+const TEMPERATURE_STEP: f32 = 0.1;
+
+/// Temperature as displayed, in `TEMPERATURE_STEP`s of the display unit: two readings with the
+/// same value look the same on screen.
+fn displayed_temperature(celsius: f32, fahrenheit: bool) -> i32 {
+    let value = if fahrenheit {
+        celsius_to_fahrenheit(celsius)
+    } else {
+        celsius
+    };
+    (value / TEMPERATURE_STEP).round() as i32
+}
+
+/// When a countdown of `duration` seconds started at `current_time` rings, `None` when that is
+/// out of chrono's range.
+fn countdown_stops_at(current_time: DateTime<Tz>, duration: u8) -> Option<DateTime<Tz>> {
+    current_time.checked_add_signed(chrono::Duration::seconds(duration.into()))
+}
+
+/// Formats seconds as "Xd Yh Zm".
+pub fn format_duration(secs: u64) -> alloc::string::String {
+    format!(
+        "{}d {}h {}m",
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60
+    )
+}
+
+/// Unpacks nested `MultipleActions` into the actions they hold, in order, without recursion.
+pub fn flatten_actions(actions: Vec<Action>) -> Vec<Action> {
+    let mut flat = Vec::with_capacity(actions.len());
+    // reversed so popping yields the actions in order
+    let mut pending: Vec<Action> = actions.into_iter().rev().collect();
+    while let Some(action) = pending.pop() {
+        match action {
+            Action::MultipleActions(nested) => pending.extend(nested.into_iter().rev()),
+            action => flat.push(action),
+        }
     }
+    flat
+}
+
+/// The actions of a `MultipleActions` to process, flattened and capped to `MAX_BATCH_ACTIONS`.
+fn bounded_batch(actions: Vec<Action>) -> Vec<Action> {
+    let mut actions = flatten_actions(actions);
+    if actions.len() > MAX_BATCH_ACTIONS {
+        warn!(
+            "dropping {} actions of a batch",
+            actions.len() - MAX_BATCH_ACTIONS
+        );
+        actions.truncate(MAX_BATCH_ACTIONS);
+    }
+    actions
 }
 
 pub fn send_action(a: Action) {
@@ -324,19 +1267,363 @@ pub fn send_action(a: Action) {
         Ok(_) => {
             // see loop in `fn run()` for dequeue
         }
-        Err(a) => {
+        Err(TrySendError::Full(a)) => {
             // this could happen because the controller is slow to respond or we are making too many requests
-            warn!("user action queue full, could not add: {:?}", a)
+            warn!(
+                "user action queue full, applying {:?} to: {:?}",
+                ACTION_BACKPRESSURE, a
+            );
+            match ACTION_BACKPRESSURE {
+                Backpressure::DropNewest => {}
+                Backpressure::DropOldest => {
+                    ACTION.try_receive().ok();
+                    ACTION.try_send(a).ok();
+                }
+                Backpressure::Coalesce => {
+                    let mut queued: Vec<Action> = Vec::with_capacity(ACTION_CHANNEL_DEPTH);
+                    while let Ok(q) = ACTION.try_receive() {
+                        queued.push(q);
+                    }
+                    match queued
+                        .iter()
+                        .position(|q| core::mem::discriminant(q) == core::mem::discriminant(&a))
+                    {
+                        Some(i) => {
+                            // the newer one goes last, behind anything queued after the old one
+                            queued.remove(i);
+                            queued.push(a);
+                        }
+                        None => {
+                            // the periodic burst carries the clock, drop a user action first
+                            let oldest = queued
+                                .iter()
+                                .position(|q| !matches!(q, Action::MultipleActions(_)))
+                                .unwrap_or(0);
+                            if !queued.is_empty() {
+                                queued.remove(oldest);
+                            }
+                            queued.push(a);
+                        }
+                    }
+                    for q in queued {
+                        ACTION.try_send(q).ok();
+                    }
+                }
+            }
         }
     }
 }
 
-pub async fn refresh_screen() -> Action {
-    let r = REFRESH_SIGNAL.receive().await;
-    REFRESH_SIGNAL.clear();
-    r
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone};
+    use chrono_tz::Europe::Paris;
+    use embassy_futures::block_on;
+    use slint::platform::software_renderer::{MinimalSoftwareWindow, RepaintBufferType};
+
+    use crate::slintplatform::EspEmbassyBackend;
+
+    std::thread_local! {
+        /// Skies computed by `UpdateTime` on the test thread.
+        pub(super) static SKY_RECOMPUTES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn paris(year: i32, month: u32, day: u32, hour: u32, min: u32) -> DateTime<Tz> {
+        Paris.with_ymd_and_hms(year, month, day, hour, min, 0).unwrap()
+    }
+
+    struct NoHardware;
+
+    impl Hardware for NoHardware {}
+
+    /// Stopped at the epoch, the tests give the time in the actions.
+    struct StoppedClock;
+
+    impl WallClock for StoppedClock {
+        async fn get_date_time(&self) -> DateTime<Utc> {
+            DateTime::UNIX_EPOCH
+        }
+
+        async fn set_date_time(&self, _datetime: DateTime<Utc>) {}
+    }
+
+    /// The UI on a window never shown, Slint needs a platform to create it.
+    fn recipe() -> Recipe {
+        let window = MinimalSoftwareWindow::new(RepaintBufferType::ReusedBuffer);
+        // once per test thread, set by an earlier test of the thread otherwise
+        let backend = alloc::boxed::Box::new(EspEmbassyBackend::new(window));
+        let _ = slint::platform::set_platform(backend);
+        Recipe::new().unwrap()
+    }
+
+    #[test]
+    fn cached_value_claims_once_per_max_age() {
+        let moon = CachedValue::new(MOON_RECOMPUTE_SECS);
+        let t0 = paris(2024, 3, 20, 21, 0);
+        assert!(moon.claim(t0));
+        assert!(!moon.claim(t0 + TimeDelta::seconds(MOON_RECOMPUTE_SECS - 1)));
+        let expired = t0 + TimeDelta::seconds(MOON_RECOMPUTE_SECS);
+        assert!(moon.claim(expired));
+        assert!(!moon.claim(expired));
+        moon.invalidate();
+        assert!(moon.claim(expired));
+    }
+
+    #[test]
+    fn update_time_burst_recomputes_the_sky_once() {
+        let recipe = recipe();
+        let mut controller = Controller::new(&recipe, NoHardware, Rc::new(StoppedClock));
+        let recomputes = || SKY_RECOMPUTES.with(Cell::get);
+        let before = recomputes();
+        let t0 = paris(2024, 3, 20, 21, 0);
+        block_on(async {
+            for ms in 0..100 {
+                let time = t0 + TimeDelta::milliseconds(ms * 10);
+                controller.process_action(Action::UpdateTime(time)).await.unwrap();
+            }
+        });
+        assert_eq!(recomputes() - before, 1);
+
+        // an invalidated sky is recomputed by the next tick
+        block_on(async {
+            let night_sky = Action::SetNightSky(NightSky::MOONLIT);
+            controller.process_action(night_sky).await.unwrap();
+            let time = t0 + TimeDelta::seconds(1);
+            controller.process_action(Action::UpdateTime(time)).await.unwrap();
+        });
+        assert_eq!(recomputes() - before, 2);
+    }
+
+    #[test]
+    fn rotate_sets_the_clock_while_adjusting_the_time() {
+        let recipe = recipe();
+        let chip = ds323x::Ds323x::new_ds3231(crate::mock_i2c::Ds3231Mock::new());
+        let clock = Rc::new(embassy_sync::mutex::Mutex::<CriticalSectionRawMutex, _>::new(chip));
+        let mut controller = Controller::new(&recipe, NoHardware, clock.clone());
+        let utc = |hour, min, sec| Utc.with_ymd_and_hms(2024, 3, 20, hour, min, sec).unwrap();
+        block_on(async {
+            clock.set_date_time(utc(21, 30, 42)).await;
+            controller.process_action(Action::ToggleTimeAdjust).await.unwrap();
+            controller.process_action(Action::Rotate(2)).await.unwrap();
+            assert_eq!(clock.get_date_time().await, utc(21, 32, 0));
+            controller.process_action(Action::Rotate(-3)).await.unwrap();
+            assert_eq!(clock.get_date_time().await, utc(21, 29, 0));
+        });
+    }
+
+    #[test]
+    fn deeply_nested_batch_is_bounded() {
+        let mut batch = Action::RefreshMoon;
+        for _ in 0..10_000 {
+            batch = Action::MultipleActions(alloc::vec![batch, Action::RefreshMoon]);
+        }
+        let Action::MultipleActions(actions) = batch else {
+            unreachable!()
+        };
+        let actions = bounded_batch(actions);
+        assert_eq!(actions.len(), MAX_BATCH_ACTIONS);
+        assert!(actions.iter().all(|a| matches!(a, Action::RefreshMoon)));
+    }
+
+    #[test]
+    fn nested_batch_is_flattened_in_order() {
+        let batch = alloc::vec![
+            Action::Rotate(1),
+            Action::MultipleActions(alloc::vec![
+                Action::Rotate(2),
+                Action::MultipleActions(alloc::vec![Action::Rotate(3)]),
+                Action::Rotate(4),
+            ]),
+            Action::MultipleActions(alloc::vec![]),
+            Action::Rotate(5),
+        ];
+        let order: Vec<i8> = flatten_actions(batch)
+            .into_iter()
+            .map(|a| match a {
+                Action::Rotate(n) => n,
+                a => panic!("unexpected {:?}", a),
+            })
+            .collect();
+        assert_eq!(order, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn countdown_past_chrono_range_is_refused() {
+        let t0 = paris(2024, 3, 20, 21, 0);
+        assert_eq!(countdown_stops_at(t0, 90), Some(t0 + TimeDelta::seconds(90)));
+        let end_of_time = Tz::UTC.from_utc_datetime(&chrono::NaiveDateTime::MAX);
+        assert_eq!(countdown_stops_at(end_of_time, 1), None);
+    }
+
+    #[test]
+    fn a_day_of_bursts_goes_through_the_action_queue() {
+        let midnight = paris(2024, 3, 20, 0, 0);
+        let mut user_actions = 0;
+        for s in 0..86_400 {
+            let time = midnight + TimeDelta::seconds(s);
+            send_action(Action::MultipleActions(alloc::vec![
+                Action::ShowMonster(true),
+                Action::ShowColon(s % 2 == 0),
+                Action::UpdateTime(time),
+            ]));
+            if s % 600 == 0 {
+                send_action(Action::NextSkyTheme);
+            }
+            // a controller slower than the bursts, the queue fills up
+            if s % 3 != 2 {
+                continue;
+            }
+            let mut shown = None;
+            while let Ok(action) = ACTION.try_receive() {
+                for action in flatten_actions(alloc::vec![action]) {
+                    match action {
+                        Action::UpdateTime(t) => shown = Some(t),
+                        Action::NextSkyTheme => user_actions += 1,
+                        _ => {}
+                    }
+                }
+            }
+            assert_eq!(shown, Some(time));
+        }
+        assert_eq!(user_actions, 144);
+    }
+
+    /// Changes of the time of day and of the monster over a day in Paris, as the local time
+    /// they happen at, with the night factor then.
+    type DayCycle = [(&'static str, TimeOfDay, MonsterEnv, f32)];
+
+    const SUMMER_SOLSTICE_CYCLE: &DayCycle = &[
+        ("00:00", TimeOfDay::NIGHT, MonsterEnv::SLEEPING, 1.0),
+        ("04:26", TimeOfDay::TWILIGHT, MonsterEnv::SLEEPING, 1.0),
+        ("04:47", TimeOfDay::TWILIGHT, MonsterEnv::HOUSE, 0.9877),
+        ("05:41", TimeOfDay::TWILIGHT, MonsterEnv::OUTSIDE, 0.2483),
+        ("06:38", TimeOfDay::DAY, MonsterEnv::OUTSIDE, 0.0),
+        ("21:41", TimeOfDay::TWILIGHT, MonsterEnv::OUTSIDE, 0.0),
+        ("22:05", TimeOfDay::TWILIGHT, MonsterEnv::HOUSE, 0.2608),
+        ("23:00", TimeOfDay::NIGHT, MonsterEnv::SLEEPING, 1.0),
+    ];
+
+    const WINTER_SOLSTICE_CYCLE: &DayCycle = &[
+        ("00:00", TimeOfDay::NIGHT, MonsterEnv::SLEEPING, 1.0),
+        ("07:38", TimeOfDay::TWILIGHT, MonsterEnv::SLEEPING, 1.0),
+        ("07:51", TimeOfDay::TWILIGHT, MonsterEnv::HOUSE, 0.9919),
+        ("08:36", TimeOfDay::TWILIGHT, MonsterEnv::OUTSIDE, 0.2455),
+        ("09:38", TimeOfDay::DAY, MonsterEnv::OUTSIDE, 0.0),
+        ("16:39", TimeOfDay::TWILIGHT, MonsterEnv::OUTSIDE, 0.0),
+        ("17:03", TimeOfDay::TWILIGHT, MonsterEnv::HOUSE, 0.2623),
+        ("17:48", TimeOfDay::NIGHT, MonsterEnv::SLEEPING, 1.0),
+    ];
+
+    /// Steps through the day a minute at a time, like `UpdateTime`, and checks each change
+    /// against `expected`. A deliberate change of the sky or of the monster rules updates the
+    /// tables above.
+    fn check_day_cycle(year: i32, month: u32, day: u32, expected: &DayCycle) {
+        let midnight = paris(year, month, day, 0, 0);
+        let mut changes = Vec::new();
+        for minute in 0..24 * 60 {
+            let time = midnight + TimeDelta::minutes(minute);
+            let (tod, night_factor, ..) =
+                crate::sky::get_slint_gradient(time.to_utc(), SKY_THEMES[0], NightSky::DARK);
+            let (env, _) = monster_env(night_factor, None);
+            if changes.last().is_none_or(|&(_, t, e, _)| (t, e) != (tod, env)) {
+                changes.push((time.format("%H:%M").to_string(), tod, env, night_factor));
+            }
+        }
+        assert_eq!(changes.len(), expected.len(), "{:?}", changes);
+        for (change, expected) in changes.iter().zip(expected) {
+            assert_eq!(
+                (change.0.as_str(), change.1, change.2),
+                (expected.0, expected.1, expected.2),
+                "{:?}",
+                changes
+            );
+            assert!((change.3 - expected.3).abs() < 1e-3, "{:?}", changes);
+        }
+    }
+
+    #[test]
+    fn summer_solstice_day_cycle() {
+        check_day_cycle(2024, 6, 21, SUMMER_SOLSTICE_CYCLE);
+    }
+
+    #[test]
+    fn winter_solstice_day_cycle() {
+        check_day_cycle(2024, 12, 21, WINTER_SOLSTICE_CYCLE);
+    }
+
+    fn evening_env(month: u32) -> MonsterEnv {
+        let evening = paris(2024, month, 21, 21, 30).to_utc();
+        let (_, night_factor, ..) =
+            crate::sky::get_slint_gradient(evening, SKY_THEMES[0], NightSky::DARK);
+        monster_env(night_factor, None).0
+    }
+
+    #[test]
+    fn monster_follows_the_sky_on_summer_and_winter_evenings() {
+        // the same hour is broad daylight in June and dark night in December
+        assert_eq!(evening_env(6), MonsterEnv::OUTSIDE);
+        assert_eq!(evening_env(12), MonsterEnv::SLEEPING);
+    }
+
+    #[test]
+    fn heap_trend_warns_under_the_threshold() {
+        let mut trend = HeapTrend::new();
+        assert!(trend.push(64 * 1024).is_none());
+        let low = trend.push(LOW_MEMORY_THRESHOLD - 1);
+        assert!(matches!(low, Some(Action::LowMemoryWarning(true))));
+        assert!(trend.push(LOW_MEMORY_THRESHOLD - 1).is_none());
+        let recovered = trend.push(64 * 1024);
+        assert!(matches!(recovered, Some(Action::LowMemoryWarning(false))));
+    }
+
+    #[test]
+    fn heap_trend_warns_on_a_steady_fall() {
+        let mut trend = HeapTrend::new();
+        let samples: Vec<usize> = (0..HEAP_TREND_SAMPLES).map(|i| (64 - i) * 1024).collect();
+        let (last, first) = samples.split_last().unwrap();
+        for heap_free in first {
+            assert!(trend.push(*heap_free).is_none());
+        }
+        assert!(matches!(trend.push(*last), Some(Action::LowMemoryWarning(true))));
+    }
+
+    #[test]
+    fn time_of_day_update_draws_the_update_time_moon() {
+        for day in [1, 8, 15, 22] {
+            let time = paris(2024, 3, day, 21, 0).to_utc();
+            let update_time = current_moon(time).build_image_sized(MOON_SIZE);
+            let time_of_day_update = moon_image(Moon::new(time));
+            assert_eq!(update_time.width(), time_of_day_update.width());
+            assert!(update_time.as_slice() == time_of_day_update.as_slice());
+        }
+    }
 
-pub fn empty_refresh_screen() {
-    REFRESH_SIGNAL.try_receive().ok();
+    #[test]
+    fn moon_is_redrawn_as_it_turns() {
+        let evening = paris(2024, 3, 20, 21, 0).to_utc();
+        assert!(moon_tilt_moved(None, evening));
+        let shown = current_moon(evening).rotation;
+        assert!(!moon_tilt_moved(Some(shown), evening));
+        assert!(!moon_tilt_moved(Some(shown), evening + TimeDelta::minutes(1)));
+        assert!(moon_tilt_moved(Some(shown), evening + TimeDelta::hours(2)));
+    }
+
+    #[test]
+    fn clock_text_follows_the_timezone_and_the_dial() {
+        let time = Utc.with_ymd_and_hms(2024, 7, 1, 19, 5, 0).unwrap();
+        let paris = Settings::default();
+        assert_eq!(clock_text(time, true, &paris).as_str(), "21:05");
+        assert_eq!(clock_text(time, false, &paris).as_str(), "21 05");
+        let twelve = Settings {
+            hour12: true,
+            ..paris
+        };
+        assert_eq!(clock_text(time, true, &twelve).as_str(), "09:05");
+        let new_york = Settings {
+            timezone: chrono_tz::America::New_York,
+            ..twelve
+        };
+        assert_eq!(clock_text(time, false, &new_york).as_str(), "03 05");
+    }
 }
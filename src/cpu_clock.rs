@@ -0,0 +1,47 @@
+//! CPU clock changed at runtime, to save power while nothing moves on screen.
+//!
+//! esp-hal only sets the clock in `esp_hal::init`. Once it has set up the 480MHz PLL for
+//! 160MHz, 80MHz is the same PLL behind another CPU divider: `set` only changes that divider,
+//! as the dynamic frequency scaling of ESP-IDF does. The peripherals keep their own clocks
+//! (APB, PLL_F80M), only the CPU speed changes.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use esp_hal::{clock::CpuClock, peripherals::PCR};
+
+/// The PLL set up by `esp_hal::init` for 160MHz, divided by 3 ahead of the CPU divider.
+const PLL_MHZ: u32 = 480;
+
+/// Current CPU clock, in MHz.
+static CURRENT_MHZ: AtomicU32 = AtomicU32::new(0);
+
+extern "C" {
+    /// ROM function keeping the ROM delays in step with the clock.
+    fn ets_update_cpu_frequency(ticks_per_us: u32);
+}
+
+/// Records the clock `esp_hal::init` was given, call it right after.
+pub fn init(clock: CpuClock) {
+    CURRENT_MHZ.store(clock as u32, Ordering::Relaxed);
+}
+
+/// The CPU clock, in MHz.
+pub fn cpu_mhz() -> u32 {
+    CURRENT_MHZ.load(Ordering::Relaxed)
+}
+
+/// Switches the CPU to `clock`, which must not be above the clock given to `esp_hal::init`:
+/// the PLL has to run already. Does nothing when the CPU is at `clock` already.
+pub fn set(clock: CpuClock) {
+    let mhz = clock as u32;
+    if CURRENT_MHZ.swap(mhz, Ordering::Relaxed) == mhz {
+        return;
+    }
+    // SAFETY: only the CPU divider changes, as in `esp32c6_rtc_freq_to_pll_mhz` of esp-hal,
+    // nothing else writes it after init
+    let pcr = unsafe { &*PCR::PTR };
+    pcr.cpu_freq_conf()
+        .modify(|_, w| unsafe { w.cpu_hs_div_num().bits((PLL_MHZ / mhz / 3 - 1) as u8) });
+    unsafe { ets_update_cpu_frequency(mhz) };
+    log::debug!("CPU clock at {}MHz", mhz);
+}
@@ -0,0 +1,96 @@
+//! Time digits drawn with tiny-skia instead of the Slint font, for smooth anti-aliased glyphs
+//! at large sizes on the low DPI panel. Enabled with `controller::SKIA_DIGITS`.
+//!
+//! The digits are seven segments with rounded ends, drawn in white: the UI colorizes the image
+//! like it colors the text.
+
+use slint::{Rgba8Pixel, SharedPixelBuffer};
+use tiny_skia::{FillRule, LineCap, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+/// Segments lit for each digit, bit 0 is the top segment (a) and bit 6 the middle one (g).
+const SEGMENTS: [u8; 10] = [0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F];
+
+/// Width of a digit, relative to its height.
+const DIGIT_WIDTH: f32 = 0.55;
+/// Thickness of a segment, relative to the digit height.
+const SEGMENT_THICKNESS: f32 = 0.12;
+/// Space between two glyphs, relative to the digit height.
+const SPACING: f32 = 0.12;
+
+/// Renders "hh:mm" as white digits on a transparent background, `size` pixels high.
+///
+/// The image is about three times as wide as it is high, mind the heap: 64 pixels high takes
+/// ~45KB, twice that while it is copied into the buffer.
+pub fn render_time_digits(hh: u32, mm: u32, size: u32) -> SharedPixelBuffer<Rgba8Pixel> {
+    let height = size as f32;
+    let digit_width = height * DIGIT_WIDTH;
+    let thickness = height * SEGMENT_THICKNESS;
+    let spacing = height * SPACING;
+    let colon_width = thickness;
+    let width = 4.0 * digit_width + colon_width + 4.0 * spacing;
+
+    let mut pixmap = Pixmap::new(width.ceil() as u32, size).unwrap();
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(255, 255, 255, 255);
+    paint.anti_alias = true;
+    let stroke = Stroke {
+        width: thickness,
+        line_cap: LineCap::Round,
+        ..Default::default()
+    };
+
+    let mut pb = PathBuilder::new();
+    let mut x = 0.0;
+    for digit in [hh / 10, hh % 10] {
+        push_digit(&mut pb, digit, x, height, thickness);
+        x += digit_width + spacing;
+    }
+    let colon_x = x + colon_width / 2.0;
+    x += colon_width + spacing;
+    for digit in [mm / 10, mm % 10] {
+        push_digit(&mut pb, digit, x, height, thickness);
+        x += digit_width + spacing;
+    }
+    if let Some(path) = pb.finish() {
+        pixmap.stroke_path(&path, &paint, &stroke, Transform::identity(), None);
+    }
+
+    let mut pb = PathBuilder::new();
+    pb.push_circle(colon_x, height * 0.3, thickness / 2.0);
+    pb.push_circle(colon_x, height * 0.7, thickness / 2.0);
+    if let Some(path) = pb.finish() {
+        pixmap.fill_path(&path, &paint, FillRule::Winding, Transform::identity(), None);
+    }
+
+    SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(pixmap.data(), pixmap.width(), size)
+}
+
+/// Appends the lit segments of `digit`, in a box starting at `x`, as lines stroked later.
+fn push_digit(pb: &mut PathBuilder, digit: u32, x: f32, height: f32, thickness: f32) {
+    let width = height * DIGIT_WIDTH;
+    // the round caps stick out by half the thickness, keep them in the box
+    let left = x + thickness / 2.0;
+    let right = x + width - thickness / 2.0;
+    let top = thickness / 2.0;
+    let bottom = height - thickness / 2.0;
+    let middle = height / 2.0;
+    // segments don't touch, like on a real display
+    let gap = thickness * 0.8;
+
+    let segments = [
+        (left + gap, top, right - gap, top), // a
+        (right, top + gap, right, middle - gap), // b
+        (right, middle + gap, right, bottom - gap), // c
+        (left + gap, bottom, right - gap, bottom), // d
+        (left, middle + gap, left, bottom - gap), // e
+        (left, top + gap, left, middle - gap), // f
+        (left + gap, middle, right - gap, middle), // g
+    ];
+    let lit = SEGMENTS[digit as usize % SEGMENTS.len()];
+    for (i, (x0, y0, x1, y1)) in segments.into_iter().enumerate() {
+        if lit & (1 << i) != 0 {
+            pb.move_to(x0, y0);
+            pb.line_to(x1, y1);
+        }
+    }
+}
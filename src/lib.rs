@@ -7,11 +7,25 @@ pub mod board;
 #[cfg(feature = "mcu")]
 pub mod boards;
 
+pub mod buttons;
+pub mod cli;
 pub mod controller;
-pub mod moon;
 #[cfg(feature = "mcu")]
+pub mod cpu_clock;
+pub mod digits;
+pub mod log_throttle;
+#[cfg(test)]
+pub mod mock_i2c;
+pub mod moon;
+#[cfg(all(feature = "mcu", not(feature = "offline")))]
 pub mod ntp;
+pub mod rtc;
+pub mod settings;
 pub mod sky;
 pub mod slintplatform;
 #[cfg(feature = "mcu")]
+pub mod tasks;
+#[cfg(all(feature = "mcu", feature = "rtc-32khz"))]
+pub mod timebase;
+#[cfg(all(feature = "mcu", not(feature = "offline")))]
 pub mod wifi;
@@ -0,0 +1,26 @@
+/// Logs at most once every `$period_ms` milliseconds per call site, for hot paths (per frame,
+/// per line...) that would otherwise flood the serial output and starve the other tasks.
+///
+/// When the trace level is enabled every call is logged, to get the full picture while debugging.
+///
+/// ```ignore
+/// log_throttled!(1000, log::Level::Debug, "Redraw l: {}", line);
+/// ```
+#[macro_export]
+macro_rules! log_throttled {
+    ($period_ms:expr, $lvl:expr, $($arg:tt)+) => {{
+        static LAST_LOG_MS: ::core::sync::atomic::AtomicU32 =
+            ::core::sync::atomic::AtomicU32::new(0);
+        if ::log::log_enabled!(::log::Level::Trace) {
+            ::log::log!($lvl, $($arg)+);
+        } else if ::log::log_enabled!($lvl) {
+            // truncated to 32 bits, wrapping_sub keeps the comparison right after ~49 days
+            let now = ::embassy_time::Instant::now().as_millis() as u32;
+            let last = LAST_LOG_MS.load(::core::sync::atomic::Ordering::Relaxed);
+            if last == 0 || now.wrapping_sub(last) >= $period_ms {
+                LAST_LOG_MS.store(now.max(1), ::core::sync::atomic::Ordering::Relaxed);
+                ::log::log!($lvl, $($arg)+);
+            }
+        }
+    }};
+}
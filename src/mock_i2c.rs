@@ -0,0 +1,161 @@
+//! A DS3231 emulated at the register level, to drive `Ds323x::new_ds3231` in tests without
+//! the hardware.
+//!
+//! Only the registers the firmware relies on behave like the chip: the BCD date time, the
+//! status register (oscillator stopped and 32kHz output flags) and the temperature. The time
+//! doesn't run by itself, it stays what was last written.
+
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+/// 7 bits address of the DS3231.
+const ADDRESS: u8 = 0x68;
+
+const REGISTER_COUNT: usize = 0x13;
+const STATUS: usize = 0x0F;
+const TEMPERATURE_MSB: usize = 0x11;
+const TEMPERATURE_LSB: usize = 0x12;
+
+/// Oscillator stopped flag, set at power on.
+const STATUS_OSF: u8 = 0b1000_0000;
+const STATUS_EN32KHZ: u8 = 0b0000_1000;
+
+pub struct Ds3231Mock {
+    registers: [u8; REGISTER_COUNT],
+    /// Register read or written next, auto incremented like on the chip.
+    pointer: usize,
+}
+
+impl Ds3231Mock {
+    /// A chip just powered on: 2000-01-01 00:00:00, oscillator stopped flag set, 25°C.
+    pub fn new() -> Self {
+        let mut registers = [0; REGISTER_COUNT];
+        registers[0x03] = 0x01; // weekday
+        registers[0x04] = 0x01; // day of month
+        registers[0x05] = 0x01; // month
+        registers[STATUS] = STATUS_OSF | STATUS_EN32KHZ;
+        let mut mock = Self {
+            registers,
+            pointer: 0,
+        };
+        mock.set_temperature(25.0);
+        mock
+    }
+
+    /// Temperature reported by the chip, with its 0.25°C resolution.
+    pub fn set_temperature(&mut self, celsius: f32) {
+        let quarters = (celsius * 4.0) as i16;
+        self.registers[TEMPERATURE_MSB] = (quarters >> 2) as u8;
+        self.registers[TEMPERATURE_LSB] = ((quarters & 0b11) as u8) << 6;
+    }
+
+    /// Sets the oscillator stopped flag, as after a battery loss.
+    pub fn stop_oscillator(&mut self) {
+        self.registers[STATUS] |= STATUS_OSF;
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let Some((&register, data)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = register as usize % REGISTER_COUNT;
+        for byte in data {
+            self.registers[self.pointer] = match self.pointer {
+                // the oscillator stopped flag can only be cleared, not set, by a write
+                STATUS => *byte & (self.registers[STATUS] | !STATUS_OSF),
+                // read only
+                TEMPERATURE_MSB | TEMPERATURE_LSB => self.registers[self.pointer],
+                _ => *byte,
+            };
+            self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = self.registers[self.pointer];
+            self.pointer = (self.pointer + 1) % REGISTER_COUNT;
+        }
+    }
+}
+
+impl ErrorType for Ds3231Mock {
+    type Error = ErrorKind;
+}
+
+impl I2c for Ds3231Mock {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if address != ADDRESS {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => self.write(bytes),
+                Operation::Read(buffer) => self.read(buffer),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use ds323x::{DateTimeAccess, Ds323x};
+
+    use super::*;
+
+    fn naive(year: i32, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|date| date.and_hms_opt(hour, min, sec))
+            .unwrap()
+    }
+
+    #[test]
+    fn date_time_round_trip() {
+        let mut rtc = Ds323x::new_ds3231(Ds3231Mock::new());
+        assert_eq!(rtc.datetime().unwrap(), naive(2000, 1, 1, 0, 0, 0));
+        let time = naive(2024, 7, 14, 23, 59, 58);
+        rtc.set_datetime(&time).unwrap();
+        assert_eq!(rtc.datetime().unwrap(), time);
+    }
+
+    #[test]
+    fn oscillator_stopped_flag() {
+        let mut rtc = Ds323x::new_ds3231(Ds3231Mock::new());
+        assert!(rtc.has_been_stopped().unwrap());
+        rtc.clear_has_been_stopped_flag().unwrap();
+        assert!(!rtc.has_been_stopped().unwrap());
+
+        let mut mock = rtc.destroy_ds3231();
+        mock.stop_oscillator();
+        let mut rtc = Ds323x::new_ds3231(mock);
+        assert!(rtc.has_been_stopped().unwrap());
+    }
+
+    #[test]
+    fn temperature() {
+        let mut mock = Ds3231Mock::new();
+        mock.set_temperature(-7.25);
+        let mut rtc = Ds323x::new_ds3231(mock);
+        assert_eq!(rtc.temperature().unwrap(), -7.25);
+
+        // read only, a write leaves it alone
+        let mut mock = rtc.destroy_ds3231();
+        I2c::write(&mut mock, ADDRESS, &[TEMPERATURE_MSB as u8, 0x40, 0x00]).unwrap();
+        assert_eq!(Ds323x::new_ds3231(mock).temperature().unwrap(), -7.25);
+    }
+
+    #[test]
+    fn other_addresses_are_not_acknowledged() {
+        let mut mock = Ds3231Mock::new();
+        assert!(I2c::write(&mut mock, 0x57, &[0x00, 0x00]).is_err());
+    }
+}
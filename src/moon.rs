@@ -3,7 +3,12 @@ use core::f32::consts::TAU;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use micromath::F32Ext;
 use slint::{Rgba8Pixel, SharedPixelBuffer};
-use tiny_skia::{FillRule, Mask, Paint, PathBuilder, Pixmap, Transform};
+use tiny_skia::{
+    Color, FillRule, GradientStop, Paint, PathBuilder, Pixmap, PixmapPaint, Point, RadialGradient,
+    Rect, SpreadMode, Transform,
+};
+
+use crate::sky::MicroMathFloatOps;
 
 /// The period of the lunar orbit in days.
 pub const ORBIT_PERIOD: f32 = 29.53058770576;
@@ -11,18 +16,60 @@ pub const ORBIT_PERIOD: f32 = 29.53058770576;
 /// The offset for the lunar orbit calculations.
 pub const ORBIT_OFFSET: f32 = 2451550.26;
 
+/// `ORBIT_PERIOD` in seconds.
+const ORBIT_PERIOD_SECS: i64 = 2_551_443;
+
+/// Unix time of `ORBIT_OFFSET`, the new moon of 2000-01-06 18:14 UTC.
+const NEW_MOON_UNIX: i64 = 947_182_464;
+
 /// The period of the lunar distance in days.
 pub const DISTANCE_PERIOD: f32 = 27.55454988;
 
 /// The offset for the lunar distance calculations.
 pub const DISTANCE_OFFSET: f32 = 2451562.2;
 
+/// The sidereal period of the moon in days, to come back in front of the same stars.
+pub const SIDEREAL_PERIOD: f32 = 27.321582241;
+
+/// The offset for the sidereal position calculations.
+pub const SIDEREAL_OFFSET: f32 = 2451555.8;
+
+/// Zodiac signs, 30° each along the ecliptic starting at the March equinox.
+pub const ZODIAC_SIGNS: [&str; 12] = [
+    "Aries",
+    "Taurus",
+    "Gemini",
+    "Cancer",
+    "Leo",
+    "Virgo",
+    "Libra",
+    "Scorpio",
+    "Sagittarius",
+    "Capricorn",
+    "Aquarius",
+    "Pisces",
+];
+
 /// The base value for calculating lunation.
 pub const LUNATION_BASE: f32 = 2423436.6115277777;
 
 /// The mean radius of the Earth in kilometers.
 pub const EARTH_RADIUS_KM: f32 = 6371.0084;
 
+/// Distance, in kilometers, under which the moon is a supermoon, close to the perigee.
+pub const SUPERMOON_DISTANCE_KM: f32 = 360_000.0;
+
+/// Distance, in kilometers, over which the moon is a micromoon, close to the apogee.
+pub const MICROMOON_DISTANCE_KM: f32 = 405_000.0;
+
+/// How large the moon looks, see `Moon::apparent_condition`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoonSizeClass {
+    SUPERMOON,
+    NORMAL,
+    MICROMOON,
+}
+
 /// Represents a lunar phase with name, emoji and start and end fractions.
 #[derive(Debug, Clone, Copy)]
 pub struct Phase {
@@ -102,6 +149,12 @@ pub struct Moon {
     pub distance: f32,
     /// Lunation number.
     pub lunation: u16,
+    /// Clockwise rotation of the drawing, in degrees, see `with_parallactic_rotation`.
+    pub rotation: f32,
+    /// Unix time the moon was computed for, kept whole as `julian_date` is hours off.
+    pub timestamp: i64,
+    /// Color of the lit part of the drawing, see `with_color`.
+    pub color: (u8, u8, u8),
 }
 
 /// Calculates the Julian date of the moon based on the provided `SystemTime`.
@@ -145,6 +198,9 @@ impl Moon {
             illumination: 0.0,
             distance: 0.0,
             lunation: 0,
+            rotation: DEFAULT_ROTATION,
+            timestamp: 0,
+            color: DEFAULT_COLOR,
         }
     }
 
@@ -163,21 +219,132 @@ impl Moon {
             illumination,
             distance,
             lunation,
+            rotation: DEFAULT_ROTATION,
+            timestamp: time.timestamp(),
+            color: DEFAULT_COLOR,
         }
     }
 
+    /// Altitude above the horizon and azimuth from the north towards the east, in degrees, as
+    /// seen from (`latitude`, `longitude`) at `time`, within a few degrees.
+    pub fn horizontal_position(
+        &self,
+        time: DateTime<Utc>,
+        latitude: f32,
+        longitude: f32,
+    ) -> (f32, f32) {
+        let (ra, dec) = equatorial(self.ecliptic_longitude());
+        let hour_angle = local_sidereal_time(time, longitude) - ra;
+        let latitude = latitude.to_radians();
+        let altitude = (latitude.sin() * dec.sin()
+            + latitude.cos() * dec.cos() * hour_angle.cos())
+        .asin();
+        // from the south towards the west
+        let azimuth = hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * latitude.sin() - dec.tan() * latitude.cos());
+        let azimuth = (azimuth.to_degrees() + 180.0) % 360.0;
+        (altitude.to_degrees(), azimuth)
+    }
+
+    /// Turns the drawing so the lit limb faces the sun as seen from (`latitude`, `longitude`)
+    /// at `time`, instead of `DEFAULT_ROTATION`. The bright limb position angle is measured
+    /// from the zenith, between the `horizontal_position` of the moon and the `spa` position of
+    /// the sun, so the tilt changes through the night. When `spa` can't place the sun, the
+    /// moon is only turned for the hemisphere, see `seen_from_latitude`.
+    pub fn with_parallactic_rotation(
+        self,
+        time: DateTime<Utc>,
+        latitude: f32,
+        longitude: f32,
+    ) -> Moon {
+        let sun = match spa::solar_position::<MicroMathFloatOps>(
+            time,
+            latitude as f64,
+            longitude as f64,
+        ) {
+            Ok(sun) => sun,
+            Err(e) => {
+                log::warn!("could not compute the sun position at {}: {:?}", time, e);
+                return self.seen_from_latitude(latitude);
+            }
+        };
+        let sun_altitude = (90.0 - sun.zenith_angle as f32).to_radians();
+        let (altitude, azimuth) = self.horizontal_position(time, latitude, longitude);
+        let altitude = altitude.to_radians();
+        let azimuth_diff = (sun.azimuth as f32 - azimuth).to_radians();
+
+        // direction of the sun from the moon, clockwise from the zenith as seen facing the moon
+        let limb = (sun_altitude.cos() * azimuth_diff.sin()).atan2(
+            sun_altitude.sin() * altitude.cos()
+                - sun_altitude.cos() * altitude.sin() * azimuth_diff.cos(),
+        );
+        let limb = limb.to_degrees();
+        // the lit limb is drawn on the right while waxing, on the left while waning
+        let rotation = if self.phase <= 0.5 {
+            limb - 90.0
+        } else {
+            limb - 270.0
+        };
+        Moon { rotation, ..self }
+    }
+
     /// Returns the distance of the moon in kilometers.
     pub fn distance_km(&self) -> f32 {
         self.distance * EARTH_RADIUS_KM
     }
 
-    /// Checks if the moon is in the waning phase.
+    /// Tells a supermoon or a micromoon, closer than `SUPERMOON_DISTANCE_KM` or farther than
+    /// `MICROMOON_DISTANCE_KM`, from a moon of normal size.
+    pub fn apparent_condition(&self) -> MoonSizeClass {
+        let distance = self.distance_km();
+        if distance < SUPERMOON_DISTANCE_KM {
+            MoonSizeClass::SUPERMOON
+        } else if distance > MICROMOON_DISTANCE_KM {
+            MoonSizeClass::MICROMOON
+        } else {
+            MoonSizeClass::NORMAL
+        }
+    }
+
+    /// Returns the age of the moon split into whole days and remaining hours,
+    /// e.g. an age of 14.75 days gives `(14, 18)`.
+    pub fn age_display(&self) -> (u8, u8) {
+        let days = self.age.floor();
+        let hours = ((self.age - days) * 24.0).floor();
+        (days as u8, hours as u8)
+    }
+
+    /// Returns the approximate ecliptic longitude of the moon in degrees (0 to 360): its
+    /// sidereal position corrected by the main perturbations, within a few degrees.
+    pub fn ecliptic_longitude(&self) -> f32 {
+        let sidereal = ((self.julian_date - SIDEREAL_OFFSET) / SIDEREAL_PERIOD).fract();
+        let phase_tau = TAU * self.phase;
+        let distance_tau = TAU * distance_phase(self.julian_date);
+        let longitude = 360.0 * sidereal
+            + 6.3 * distance_tau.sin()
+            + 1.3 * (2.0 * phase_tau - distance_tau).sin()
+            + 0.7 * (2.0 * phase_tau).sin();
+        let longitude = longitude % 360.0;
+        if longitude < 0.0 {
+            longitude + 360.0
+        } else {
+            longitude
+        }
+    }
+
+    /// Returns the zodiac sign the moon is in, see `ZODIAC_SIGNS`.
+    pub fn zodiac_sign(&self) -> &'static str {
+        ZODIAC_SIGNS[(self.ecliptic_longitude() / 30.0) as usize % ZODIAC_SIGNS.len()]
+    }
+
+    /// Checks if the moon is in the waning phase, from full moon to new moon.
     pub fn is_waning(&self) -> bool {
-        self.age < 0.5
+        self.phase >= 0.5
     }
-    /// Checks if the moon is in the waxing phase.
+    /// Checks if the moon is in the waxing phase, from new moon to full moon.
     pub fn is_waxing(&self) -> bool {
-        self.age > 0.5
+        self.phase < 0.5
     }
     /// Returns the name of the moon phase.
     pub fn phase_name(&self) -> &'static str {
@@ -189,6 +356,61 @@ impl Moon {
         "Unknown"
     }
 
+    /// Returns how far (0 to 1) the moon is through its current phase of `PHASES`,
+    /// 1 meaning the next phase is about to start. Handy to feed a progress bar.
+    pub fn phase_fraction_to_next(&self) -> f32 {
+        for phase in PHASES.iter() {
+            if self.phase >= phase.start && self.phase < phase.end {
+                return (self.phase - phase.start) / (phase.end - phase.start);
+            }
+        }
+        0.0
+    }
+
+    /// Returns how far (0 to 1) the moon is through the synodic month: 0 at new moon, 0.5 at
+    /// full moon, back to 0 at the next new moon. Unlike `phase_fraction_to_next` it doesn't
+    /// depend on the `PHASES` boundaries.
+    pub fn cycle_fraction(&self) -> f32 {
+        self.phase
+    }
+
+    /// Returns the days left until the next full moon, 0 right at full moon.
+    pub fn days_until_full(&self) -> f32 {
+        let full_age = ORBIT_PERIOD / 2.0;
+        if self.age <= full_age {
+            full_age - self.age
+        } else {
+            ORBIT_PERIOD - self.age + full_age
+        }
+    }
+
+    /// Returns the days left until the next new moon, 0 right at new moon.
+    pub fn days_until_new(&self) -> f32 {
+        (ORBIT_PERIOD - self.age) % ORBIT_PERIOD
+    }
+
+    /// Returns when the next full moon starts, per the mean lunation of `phase`.
+    pub fn next_full_moon(&self) -> DateTime<Utc> {
+        self.next_phase(0.5)
+    }
+
+    /// Returns when the next new moon starts, per the mean lunation of `phase`.
+    pub fn next_new_moon(&self) -> DateTime<Utc> {
+        self.next_phase(0.0)
+    }
+
+    /// Returns the first time strictly after `timestamp` the moon reaches `phase`, counted
+    /// in whole seconds as a f32 julian date is hours off.
+    fn next_phase(&self, phase: f32) -> DateTime<Utc> {
+        let into_lunation = (self.timestamp - NEW_MOON_UNIX).rem_euclid(ORBIT_PERIOD_SECS);
+        let target = (phase * ORBIT_PERIOD_SECS as f32) as i64;
+        let wait = match (target - into_lunation).rem_euclid(ORBIT_PERIOD_SECS) {
+            0 => ORBIT_PERIOD_SECS,
+            wait => wait,
+        };
+        DateTime::from_timestamp(self.timestamp + wait, 0).unwrap_or_default()
+    }
+
     /// Returns the emoji representation of the moon phase.
     pub fn phase_emoji(&self) -> &'static str {
         for phase in PHASES.iter() {
@@ -200,52 +422,462 @@ impl Moon {
     }
 
     pub fn build_image(self) -> SharedPixelBuffer<Rgba8Pixel> {
+        self.build_image_sized(DEFAULT_SIZE)
+    }
+
+    /// Draws the moon on a transparent `size` x `size` image.
+    pub fn build_image_sized(self, size: u32) -> SharedPixelBuffer<Rgba8Pixel> {
+        let mut pixmap = self.draw(size);
+        SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(pixmap.data_mut(), size, size)
+    }
+
+    /// Paints the lit part of the drawing, and its halo, in `color` instead of `DEFAULT_COLOR`:
+    /// white, red for an eclipse...
+    pub fn with_color(self, color: (u8, u8, u8)) -> Moon {
+        Moon { color, ..self }
+    }
+
+    /// Draws the moon as seen from `latitude` degrees, see `seen_from_latitude`.
+    pub fn build_image_for_latitude(self, latitude: f32) -> SharedPixelBuffer<Rgba8Pixel> {
+        self.seen_from_latitude(latitude).build_image()
+    }
+
+    /// Below the equator the moon shows upside down: the drawing is turned half a turn, the
+    /// waxing side on the left. Unchanged from the equator up.
+    pub fn seen_from_latitude(self, latitude: f32) -> Moon {
+        if latitude < 0.0 {
+            Moon {
+                rotation: self.rotation + 180.0,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    /// Draws the `size` pixels moon, with a soft halo, onto a `width` x `height` buffer filled
+    /// with `sky`, its center at (`x`, `y`). The scene is then positioned and lit from Rust
+    /// instead of layering the moon image over the sky brush in Slint.
+    pub fn composite_on_sky(
+        self,
+        sky: Color,
+        width: u32,
+        height: u32,
+        x: f32,
+        y: f32,
+        size: u32,
+    ) -> SharedPixelBuffer<Rgba8Pixel> {
+        let radius = size as f32 / 2.0;
+        let mut pixmap = Pixmap::new(width, height).unwrap();
+        pixmap.fill(sky);
+
+        // the halo is as bright as the moon is lit
+        let glow = (HALO_ALPHA * self.illumination) as u8;
+        let (r, g, b) = self.color;
+        let halo = RadialGradient::new(
+            Point::from_xy(x, y),
+            Point::from_xy(x, y),
+            radius * HALO_RADIUS,
+            alloc::vec![
+                GradientStop::new(0.0, Color::from_rgba8(r, g, b, glow)),
+                GradientStop::new(1.0, Color::from_rgba8(r, g, b, 0)),
+            ],
+            SpreadMode::Pad,
+            Transform::identity(),
+        );
+        if let Some(shader) = halo {
+            let paint = Paint {
+                shader,
+                anti_alias: true,
+                ..Default::default()
+            };
+            let rect = Rect::from_xywh(0.0, 0.0, width as f32, height as f32).unwrap();
+            pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+        }
+
+        let moon = self.draw(size);
+        pixmap.draw_pixmap(
+            (x - radius) as i32,
+            (y - radius) as i32,
+            moon.as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+
+        SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(pixmap.data(), width, height)
+    }
+
+    /// Draws the lit part of the moon on a transparent `size` x `size` pixmap.
+    fn draw(&self, size: u32) -> Pixmap {
         let mut full_moon_paint = Paint::default();
-        full_moon_paint.set_color_rgba8(255, 246, 153, 255);
+        let (r, g, b) = self.color;
+        full_moon_paint.set_color_rgba8(r, g, b, 255);
         full_moon_paint.anti_alias = true;
 
-        let mut pixmap = Pixmap::new(34, 34).unwrap();
+        let mut pixmap = Pixmap::new(size, size).unwrap();
 
-        let mut computed = (34.0 * (self.illumination));
-        if (self.phase > 0.5) {
-            computed = computed + 34. / 2. as f32
+        let radius = size as f32 / 2.0;
+        // lit limb is on the right while waxing, on the left while waning
+        let side = if self.phase <= 0.5 { 1.0 } else { -1.0 };
+        // the terminator is half an ellipse whose minor axis follows cos(phase angle):
+        // on the limb at new moon, straight at quarters, on the opposite limb at full moon
+        let terminator = side * radius * (TAU * self.phase).cos();
+        // around new and full moon the crescents get thinner than a pixel and would only leave
+        // an anti-aliasing ring on the limb: snap them to a dark or fully lit disk
+        let lit_width = (side * radius - terminator).abs();
+        let terminator = if lit_width > 2.0 * radius - MIN_CRESCENT_WIDTH {
+            -side * radius
         } else {
-            computed = 34. / 2. - computed as f32
-        }
-        let shadow =
-            PathBuilder::from_circle(computed, (34.0 / 2.0) as f32, (34 / 2) as f32).unwrap();
+            terminator
+        };
 
         log::info!(
-            "phase: {}, computed: {}, emoji: {}",
+            "phase: {}, terminator: {}, emoji: {}",
             self.phase,
-            computed,
+            terminator,
             self.phase_emoji()
         );
 
-        let full_moon =
-            PathBuilder::from_circle((34.0 / 2.0) as f32, (34.0 / 2.0) as f32, (34 / 2) as f32)
-                .unwrap();
+        // the dark side of a thin crescent is faintly lit by the earth
+        if self.illumination < EARTHSHINE_ILLUMINATION {
+            let mut earthshine_paint = Paint::default();
+            let alpha = (255.0 * (1.0 - self.illumination)) as u8;
+            earthshine_paint.set_color_rgba8(40, 45, 70, alpha);
+            earthshine_paint.anti_alias = true;
+            if let Some(disk) = PathBuilder::from_circle(radius, radius, radius) {
+                pixmap.fill_path(
+                    &disk,
+                    &earthshine_paint,
+                    FillRule::Winding,
+                    Transform::identity(),
+                    None,
+                );
+            }
+        }
 
-        let mut mask = Mask::new(34, 34).unwrap();
-        mask.fill_path(
-            &shadow,
-            FillRule::Winding,
-            true,
-            Transform::from_rotate_at(-25.0, 34. / 2., 34. / 2.),
-        );
-        mask.invert();
-
-        // let t = Transform::from_rotate(-20.0);
-        // pixmap.fill(Color::from_rgba8(2, 4, 38, 255));
-        pixmap.fill_path(
-            &full_moon,
-            &full_moon_paint,
-            FillRule::Winding,
-            Transform::identity(),
-            Some(&mask),
-        );
+        let mut pb = PathBuilder::new();
+        pb.move_to(radius, 0.0);
+        half_ellipse_to(&mut pb, radius, radius, side * radius, radius, 1.0);
+        half_ellipse_to(&mut pb, radius, radius, terminator, radius, -1.0);
+        pb.close();
+
+        // nothing lit at new moon
+        let lit = pb.finish().filter(|_| lit_width >= MIN_CRESCENT_WIDTH);
+        if let Some(lit) = lit {
+            pixmap.fill_path(
+                &lit,
+                &full_moon_paint,
+                FillRule::Winding,
+                Transform::from_rotate_at(self.rotation, radius, radius),
+                None,
+            );
+        }
+
+        pixmap
+    }
+}
+
+/// Warm yellow of the lit part of the moon.
+pub const DEFAULT_COLOR: (u8, u8, u8) = (255, 246, 153);
+
+/// Side, in pixels, of the image of `Moon::build_image`.
+pub const DEFAULT_SIZE: u32 = 34;
+
+/// Rotation of the moon drawing, in degrees clockwise, a typical tilt seen from Paris.
+pub const DEFAULT_ROTATION: f32 = -25.0;
+
+/// Obliquity of the ecliptic, in degrees.
+const OBLIQUITY: f32 = 23.439;
+
+/// Unix time of J2000.0, 2000-01-01 12:00 UTC.
+const J2000_UNIX: i64 = 946_728_000;
+
+/// Right ascension and declination, in radians, of a point of the ecliptic at `longitude`
+/// degrees.
+fn equatorial(longitude: f32) -> (f32, f32) {
+    let longitude = longitude.to_radians();
+    let obliquity = OBLIQUITY.to_radians();
+    let ra = (longitude.sin() * obliquity.cos()).atan2(longitude.cos());
+    let dec = (obliquity.sin() * longitude.sin()).asin();
+    (ra, dec)
+}
+
+/// Local mean sidereal time at `longitude` degrees east, in radians.
+fn local_sidereal_time(time: DateTime<Utc>, longitude: f32) -> f32 {
+    // whole days and their fraction apart, a f32 count of days would be minutes off
+    let secs = time.timestamp() - J2000_UNIX;
+    let days = secs.div_euclid(86400) as f32;
+    let fraction = secs.rem_euclid(86400) as f32 / 86400.0;
+    let degrees = 280.460_62 + 360.0 * fraction + 0.985_647_4 * (days + fraction) + longitude;
+    (degrees % 360.0).to_radians()
+}
+
+/// Radius of the halo of `composite_on_sky`, relative to the moon radius.
+const HALO_RADIUS: f32 = 2.0;
+/// Opacity, out of 255, of the halo around a full moon.
+const HALO_ALPHA: f32 = 96.0;
+
+/// Illumination under which the dark side of the moon is drawn with earthshine, 0.0 to never
+/// draw it.
+pub const EARTHSHINE_ILLUMINATION: f32 = 0.1;
+
+/// Width, in pixels, under which a lit or dark crescent isn't drawn.
+const MIN_CRESCENT_WIDTH: f32 = 0.5;
+
+/// Magic number to approximate a quarter of ellipse with a cubic bézier curve.
+const KAPPA: f32 = 0.552_284_8;
+
+/// Appends half an ellipse centered on (`cx`, `cy`), going from the top to the bottom when
+/// `direction` is 1.0 or from the bottom to the top when it is -1.0. `rx` is signed, a negative
+/// value bulges to the left.
+fn half_ellipse_to(pb: &mut PathBuilder, cx: f32, cy: f32, rx: f32, ry: f32, direction: f32) {
+    let ry = ry * direction;
+    pb.cubic_to(
+        cx + KAPPA * rx,
+        cy - ry,
+        cx + rx,
+        cy - KAPPA * ry,
+        cx + rx,
+        cy,
+    );
+    pb.cubic_to(
+        cx + rx,
+        cy + KAPPA * ry,
+        cx + KAPPA * rx,
+        cy + ry,
+        cx,
+        cy + ry,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone};
+
+    /// Paris, where the clock is.
+    const LATITUDE: f32 = 48.866667;
+    const LONGITUDE: f32 = 2.333333;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap()
+    }
+
+    /// A moon at `phase`, with its age and illumination, drawn upright.
+    fn at_phase(phase: f32) -> Moon {
+        Moon {
+            age: phase * ORBIT_PERIOD,
+            illumination: illumination(phase),
+            rotation: 0.0,
+            ..Moon::test(phase)
+        }
+    }
+
+    fn pixel(image: &SharedPixelBuffer<Rgba8Pixel>, x: u32, y: u32) -> Rgba8Pixel {
+        image.as_slice()[(y * image.width() + x) as usize]
+    }
+
+    /// Lit by the sun, the earthshine is far darker than any moon color.
+    fn is_lit(pixel: Rgba8Pixel) -> bool {
+        pixel.a == 255 && pixel.r > 128
+    }
+
+    fn lit_pixels(image: &SharedPixelBuffer<Rgba8Pixel>) -> usize {
+        image.as_slice().iter().filter(|p| is_lit(**p)).count()
+    }
+
+    #[test]
+    fn parallactic_rotation_follows_the_time() {
+        let rotation = |time| {
+            Moon::new(time)
+                .with_parallactic_rotation(time, LATITUDE, LONGITUDE)
+                .rotation
+        };
+        // a waxing moon rising in the evening and setting after midnight
+        let evening = rotation(utc(2024, 3, 20, 20));
+        let night = rotation(utc(2024, 3, 21, 2));
+        assert!((evening - night).abs() > 10.0, "{} vs {}", evening, night);
+    }
+
+    #[test]
+    fn age_display_splits_days_and_hours() {
+        assert_eq!(Moon { age: 14.75, ..Moon::test(0.5) }.age_display(), (14, 18));
+        assert_eq!(Moon::test(0.0).age_display(), (0, 0));
+    }
 
-        let i = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(pixmap.data_mut(), 34, 34);
-        i
+    #[test]
+    fn quarter_terminator_is_straight() {
+        let image = at_phase(0.25).build_image();
+        let center = DEFAULT_SIZE / 2;
+        // half of the disk is lit, split by the vertical diameter
+        for y in center - 12..=center + 12 {
+            assert!(is_lit(pixel(&image, center + 1, y)), "row {}", y);
+            assert_eq!(pixel(&image, center - 2, y).a, 0, "row {}", y);
+        }
+    }
+
+    #[test]
+    fn phase_fraction_grows_through_each_phase() {
+        for phase in PHASES {
+            let fractions: Vec<f32> = (0..10)
+                .map(|i| phase.start + (phase.end - phase.start) * i as f32 / 10.0)
+                .map(|p| Moon::test(p).phase_fraction_to_next())
+                .collect();
+            assert!(fractions[0].abs() < 1e-3, "{}: {:?}", phase.name, fractions);
+            assert!(fractions.windows(2).all(|w| w[0] < w[1]), "{}", phase.name);
+            assert!(fractions.iter().all(|f| (0.0..1.0).contains(f)), "{}", phase.name);
+        }
+    }
+
+    #[test]
+    fn new_moon_is_dark() {
+        for phase in [0.0, 0.005, 0.995] {
+            assert_eq!(lit_pixels(&at_phase(phase).build_image()), 0, "phase {}", phase);
+        }
+    }
+
+    #[test]
+    fn full_moon_is_lit_without_a_dark_ring() {
+        let image = at_phase(0.5).build_image();
+        let radius = DEFAULT_SIZE as f32 / 2.0;
+        let disk = core::f32::consts::PI * radius * radius;
+        assert!(lit_pixels(&image) as f32 > 0.9 * disk);
+        // anti-aliasing only touches the outermost pixels of the limb
+        for y in 0..DEFAULT_SIZE {
+            for x in 0..DEFAULT_SIZE {
+                let (dx, dy) = (x as f32 + 0.5 - radius, y as f32 + 0.5 - radius);
+                if (dx * dx + dy * dy).sqrt() < radius - 1.0 {
+                    assert!(is_lit(pixel(&image, x, y)), "({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zodiac_sign_at_known_positions() {
+        // (date, ecliptic longitude of the moon, sign), from ephemerides
+        let known = [
+            (utc(2024, 1, 11, 12), 290.7, "Capricorn"),
+            (utc(2024, 4, 8, 18), 19.2, "Aries"),
+            (utc(2024, 9, 3, 2), 161.1, "Virgo"),
+        ];
+        for (time, longitude, sign) in known {
+            let moon = Moon::new(time);
+            assert!((moon.ecliptic_longitude() - longitude).abs() < 3.0, "{}", time);
+            assert_eq!(moon.zodiac_sign(), sign, "{}", time);
+        }
+    }
+
+    #[test]
+    fn cycle_fraction_and_days_until() {
+        // (phase, days until full, days until new)
+        let expected = [
+            (0.0, 0.5, 0.0),
+            (0.25, 0.25, 0.75),
+            (0.5, 0.0, 0.5),
+            (0.75, 0.75, 0.25),
+        ];
+        for (phase, until_full, until_new) in expected {
+            let moon = at_phase(phase);
+            assert_eq!(moon.cycle_fraction(), phase);
+            assert!((moon.days_until_full() - until_full * ORBIT_PERIOD).abs() < 1e-3);
+            assert!((moon.days_until_new() - until_new * ORBIT_PERIOD).abs() < 1e-3);
+        }
+    }
+
+    /// Lit pixels in the left and right halves of the image.
+    fn lit_halves(image: &SharedPixelBuffer<Rgba8Pixel>) -> (usize, usize) {
+        let width = image.width() as usize;
+        let lit = image.as_slice().iter().enumerate().filter(|(_, p)| is_lit(**p));
+        lit.fold((0, 0), |(left, right), (i, _)| {
+            if i % width < width / 2 {
+                (left + 1, right)
+            } else {
+                (left, right + 1)
+            }
+        })
+    }
+
+    #[test]
+    fn first_quarter_flips_south_of_the_equator() {
+        let quarter = Moon::test(0.25);
+        let (north_left, north_right) = lit_halves(&quarter.build_image_for_latitude(48.0));
+        let (south_left, south_right) = lit_halves(&quarter.build_image_for_latitude(-33.0));
+        assert!(north_right > 4 * north_left);
+        assert!(south_left > 4 * south_right);
+        // half a turn: the columns lit on one side are lit on the other
+        assert!(north_right.abs_diff(south_left) <= 2);
+        assert!(north_left.abs_diff(south_right) <= 2);
+    }
+
+    #[test]
+    fn image_has_the_requested_size() {
+        for size in [20, DEFAULT_SIZE, 68, 120] {
+            let image = Moon::test(0.3).build_image_sized(size);
+            assert_eq!((image.width(), image.height()), (size, size));
+            assert_eq!(image.as_slice().len(), (size * size) as usize);
+        }
+    }
+
+    #[test]
+    fn waxing_after_new_moon_waning_after_full_moon() {
+        let after_new = Moon::test(0.02);
+        assert!(after_new.is_waxing() && !after_new.is_waning());
+        let after_full = Moon::test(0.52);
+        assert!(after_full.is_waning() && !after_full.is_waxing());
+    }
+
+    #[test]
+    fn earthshine_lights_thin_crescents_only() {
+        let center = DEFAULT_SIZE / 2;
+        let thin = at_phase(0.02).build_image();
+        assert!(pixel(&thin, center, center).a > 0);
+        // the dark side of a quarter stays transparent
+        let quarter = at_phase(0.25).build_image();
+        assert_eq!(pixel(&quarter, center - 4, center).a, 0);
+    }
+
+    #[test]
+    fn next_full_and_new_moons_of_2024() {
+        // the mean lunation drifts up to ~14 hours from the true one, these two are close to it
+        let within_two_hours = |predicted: DateTime<Utc>, actual: DateTime<Utc>| {
+            (predicted - actual).num_minutes().abs() <= 120
+        };
+        let new_moon = Utc.with_ymd_and_hms(2024, 3, 10, 9, 0, 0).unwrap();
+        let predicted = Moon::new(utc(2024, 3, 5, 0)).next_new_moon();
+        assert!(within_two_hours(predicted, new_moon), "{}", predicted);
+        let full_moon = Utc.with_ymd_and_hms(2024, 8, 19, 18, 26, 0).unwrap();
+        let predicted = Moon::new(utc(2024, 8, 14, 0)).next_full_moon();
+        assert!(within_two_hours(predicted, full_moon), "{}", predicted);
+    }
+
+    #[test]
+    fn next_phase_is_within_a_lunation() {
+        let now = utc(2024, 5, 1, 0);
+        let moon = Moon::new(now);
+        for next in [moon.next_full_moon(), moon.next_new_moon()] {
+            assert!(next > now && next <= now + TimeDelta::seconds(ORBIT_PERIOD_SECS));
+        }
+    }
+
+    #[test]
+    fn apparent_condition_at_perigee_and_apogee() {
+        let at_km = |km: f32| Moon {
+            distance: km / EARTH_RADIUS_KM,
+            ..Moon::test(0.5)
+        };
+        assert_eq!(at_km(356_500.0).apparent_condition(), MoonSizeClass::SUPERMOON);
+        assert_eq!(at_km(384_400.0).apparent_condition(), MoonSizeClass::NORMAL);
+        assert_eq!(at_km(406_700.0).apparent_condition(), MoonSizeClass::MICROMOON);
+    }
+
+    #[test]
+    fn full_moon_center_has_the_requested_color() {
+        let center = DEFAULT_SIZE / 2;
+        for (r, g, b) in [DEFAULT_COLOR, (255, 255, 255), (200, 30, 20)] {
+            let image = at_phase(0.5).with_color((r, g, b)).build_image();
+            assert_eq!(pixel(&image, center, center), Rgba8Pixel { r, g, b, a: 255 });
+        }
     }
 }
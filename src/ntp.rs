@@ -1,9 +1,11 @@
+use core::cell::Cell;
 use core::net::{IpAddr, SocketAddr};
 
 use alloc::rc::Rc;
 use chrono::{offset, DateTime, NaiveDateTime, TimeDelta, Utc};
 use embassy_net::{udp::UdpSocket, Stack};
-use embassy_sync::{channel::Channel, signal::Signal};
+use embassy_sync::{blocking_mutex::CriticalSectionMutex, channel::Channel, signal::Signal};
+use embassy_futures::select::select;
 use embassy_time::{Duration, Instant, Timer};
 use smoltcp::{storage::PacketMetadata, wire::DnsQueryType};
 use sntpc::{get_time, NtpContext, NtpTimestampGenerator};
@@ -12,10 +14,25 @@ use crate::controller::{Hardware, WallClock};
 
 const NTP_SERVER: &str = "pool.ntp.org";
 
+/// Wait before asking again after a failed request, instead of the usual 15 minutes.
+const NTP_RETRY_DELAY: Duration = Duration::from_secs(30);
+
 type DateTimeSource =
     Signal<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, DateTime<Utc>>;
 pub static DATE_SOURCE: DateTimeSource = Signal::new();
 
+/// Cuts the wait between two NTP requests short.
+pub static SYNC_REQUEST: Signal<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, ()> =
+    Signal::new();
+
+static LAST_SYNC: CriticalSectionMutex<Cell<Option<Instant>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+/// When the last successful NTP request happened, `None` before the first one.
+pub fn last_sync() -> Option<Instant> {
+    LAST_SYNC.lock(|l| l.get())
+}
+
 #[derive(Copy, Clone)]
 struct Timestamp {
     duration: Duration,
@@ -145,6 +162,7 @@ impl<'a> NtpClient<'a> {
                     //         - now;
                     let delta_ntp = datetime - start;
                     DATE_SOURCE.signal(datetime);
+                    LAST_SYNC.lock(|l| l.set(Some(Instant::now())));
                     log::info!(
                         "Time: {:?}, offset: {}, roundtrip: {}",
                         datetime,
@@ -165,10 +183,14 @@ impl<'a> NtpClient<'a> {
                 }
                 Err(e) => {
                     log::error!("Error getting time: {:?}", e);
+                    // e.g. the link dropped while the WiFi switches networks, retry soon
+                    select(Timer::after(NTP_RETRY_DELAY), SYNC_REQUEST.wait()).await;
+                    continue;
                 }
             }
 
-            Timer::after(Duration::from_secs(15 * 60)).await; // Every 15 minutes
+            // Every 15 minutes, or sooner when asked
+            select(Timer::after(Duration::from_secs(15 * 60)), SYNC_REQUEST.wait()).await;
         }
     }
 
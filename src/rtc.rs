@@ -0,0 +1,136 @@
+use core::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+use ds323x::DateTimeAccess;
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+
+use crate::controller::WallClock;
+
+/// Any `DateTimeAccess` RTC (DS3231, DS1307...) shared behind a mutex is a `WallClock`.
+///
+/// The chip stores a naive date time, which is always UTC: conversion to a local timezone is
+/// left to the consumers, this is the only place where the naive value is interpreted.
+impl<M, RTC> WallClock for Mutex<M, RTC>
+where
+    M: RawMutex,
+    RTC: DateTimeAccess,
+    RTC::Error: Debug,
+{
+    async fn get_date_time(&self) -> DateTime<Utc> {
+        match self.lock().await.datetime() {
+            Ok(naive) => naive.and_utc(),
+            Err(e) => {
+                log::error!("could not read RTC: {:?}", e);
+                DateTime::from_timestamp_nanos(0)
+            }
+        }
+    }
+
+    async fn set_date_time(&self, datetime: DateTime<Utc>) {
+        if let Err(e) = self.lock().await.set_datetime(&datetime.naive_utc()) {
+            log::error!("could not set RTC to {}: {:?}", datetime, e);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "simulator"))]
+mod tests {
+    use chrono::{TimeDelta, TimeZone, Timelike};
+    use chrono_tz::Europe::Paris;
+    use ds323x::{ic::DS3231, interface::I2cInterface, Ds323x};
+    use embassy_futures::{block_on, join::join, yield_now};
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+    use super::*;
+    use crate::mock_i2c::Ds3231Mock;
+
+    type MockRtc = Mutex<CriticalSectionRawMutex, Ds323x<I2cInterface<Ds3231Mock>, DS3231>>;
+
+    fn mock_rtc() -> MockRtc {
+        Mutex::new(Ds323x::new_ds3231(Ds3231Mock::new()))
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn wall_clock_reads_the_rtc_as_utc() {
+        let rtc = mock_rtc();
+        // what a DS3231 holds at power on
+        assert_eq!(block_on(rtc.get_date_time()), utc(2000, 1, 1, 0, 0));
+        let time = utc(2024, 3, 20, 21, 30);
+        block_on(rtc.set_date_time(time));
+        assert_eq!(block_on(rtc.get_date_time()), time);
+    }
+
+    #[test]
+    fn paris_hour_through_the_wall_clock() {
+        let rtc = mock_rtc();
+        // (UTC time, hour in Paris), CET in winter and CEST in summer
+        let expected = [
+            (utc(2024, 1, 15, 12, 0), 13),
+            (utc(2024, 7, 1, 12, 0), 14),
+            // a minute before and after the switch to summer time
+            (utc(2024, 3, 31, 0, 59), 1),
+            (utc(2024, 3, 31, 1, 0), 3),
+        ];
+        for (time, hour) in expected {
+            block_on(rtc.set_date_time(time));
+            let local = block_on(rtc.get_date_time()).with_timezone(&Paris);
+            assert_eq!(local.hour(), hour, "{}", local);
+        }
+    }
+
+    #[test]
+    fn reads_wait_for_a_write_in_progress() {
+        let rtc = mock_rtc();
+        let start = utc(2024, 3, 20, 21, 30);
+        // every write changes the seconds, minutes and hours registers
+        let written: Vec<DateTime<Utc>> = (0..100)
+            .map(|i| start + TimeDelta::seconds(3_661 * i))
+            .collect();
+        let writes = async {
+            for time in &written {
+                // a slow writer holding the chip across an await, the date set before the time
+                let mut chip = rtc.lock().await;
+                let midnight = time.date_naive().and_hms_opt(0, 0, 0).unwrap();
+                chip.set_datetime(&midnight).unwrap();
+                yield_now().await;
+                chip.set_datetime(&time.naive_utc()).unwrap();
+                drop(chip);
+                yield_now().await;
+            }
+        };
+        let reads = async {
+            let mut read = Vec::new();
+            let mut waited = 0;
+            for _ in 0..300 {
+                if rtc.try_lock().is_err() {
+                    waited += 1;
+                }
+                read.push(rtc.get_date_time().await);
+                yield_now().await;
+            }
+            (read, waited)
+        };
+        let (_, (read, waited)) = block_on(join(writes, reads));
+        // the reads did run into writes in progress, and never saw a half written time
+        assert!(waited > 0);
+        assert!(read.iter().all(|time| written.contains(time)));
+        assert!(read.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(read.last(), written.last());
+    }
+
+    #[test]
+    fn time_survives_a_reset() {
+        let time = utc(2024, 3, 20, 21, 30);
+        let rtc = mock_rtc();
+        block_on(rtc.set_date_time(time));
+
+        // a software reset only restarts the MCU, the battery backed chip keeps counting
+        let chip = rtc.into_inner().destroy_ds3231();
+        let rtc: MockRtc = Mutex::new(Ds323x::new_ds3231(chip));
+        assert_eq!(block_on(rtc.get_date_time()), time);
+    }
+}
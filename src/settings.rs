@@ -0,0 +1,355 @@
+//! User settings that survive reboots.
+//!
+//! The current settings live in memory, `update` stores them and asks the persisting task
+//! (see `FlashSettingsStore`) to write them, so callers never block on flash.
+//!
+//! Layout of the serialized settings:
+//!
+//! | byte | content                                   |
+//! |------|-------------------------------------------|
+//! | 0    | `MAGIC`                                   |
+//! | 1    | layout version                            |
+//! | 2    | `n`, number of field bytes that follow    |
+//! | 3..  | fields, in declaration order              |
+//! | 3+n  | checksum, wrapping sum of bytes 0..3+n    |
+//!
+//! New fields are only ever appended: a newer firmware reading an older layout keeps the
+//! defaults for the fields it doesn't find, an older firmware ignores the extra bytes.
+
+use core::cell::Cell;
+
+use chrono_tz::{Europe, Tz};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, CriticalSectionMutex},
+    signal::Signal,
+};
+
+const MAGIC: u8 = 0xC1;
+
+/// Bump when fields are appended to `Settings`.
+pub const SETTINGS_VERSION: u8 = 6;
+
+const HEADER_LEN: usize = 3;
+const FIELDS_LEN: usize = 50;
+/// Room for the longest IANA timezone name, zero padded.
+const TIMEZONE_NAME_LEN: usize = 32;
+
+/// Size of the serialized settings, header and checksum included.
+pub const SERIALIZED_LEN: usize = HEADER_LEN + FIELDS_LEN + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// Index in `sky::SKY_THEMES`.
+    pub sky_theme: u8,
+    /// Countdown duration started by the buttons, in seconds.
+    pub countdown_preset: u8,
+    /// Display rotation, in quarter turns (0 to 3). Since version 2.
+    pub rotation: u8,
+    /// High contrast layout for low vision. Since version 3.
+    pub high_contrast: bool,
+    /// Day celebrated by the special date banner, as (month, day). Since version 4.
+    pub birthday: Option<(u8, u8)>,
+    /// Temperatures shown in Fahrenheit instead of Celsius. Since version 5.
+    pub fahrenheit: bool,
+    /// Time shown on a 12 hours dial instead of 24. Since version 6.
+    pub hour12: bool,
+    /// Local hours the backlight is at full brightness, from the first one to before the
+    /// second one. Since version 6.
+    pub backlight_day_hours: (u8, u8),
+    /// Where the clock is, as (latitude, longitude) in degrees, for the sun and the moon.
+    /// Since version 6.
+    pub location: (f32, f32),
+    /// Timezone of the time shown, the RTC keeps UTC. Since version 6.
+    pub timezone: Tz,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            sky_theme: 0,
+            countdown_preset: 120,
+            rotation: 0,
+            high_contrast: false,
+            birthday: None,
+            fahrenheit: false,
+            hour12: false,
+            backlight_day_hours: (9, 20),
+            location: (crate::sky::LATITUDE as f32, crate::sky::LONGITUDE as f32),
+            timezone: Europe::Paris,
+        }
+    }
+}
+
+impl Settings {
+    pub fn to_bytes(&self) -> [u8; SERIALIZED_LEN] {
+        let mut bytes = [0; SERIALIZED_LEN];
+        bytes[0] = MAGIC;
+        bytes[1] = SETTINGS_VERSION;
+        bytes[2] = FIELDS_LEN as u8;
+        bytes[3] = self.sky_theme;
+        bytes[4] = self.countdown_preset;
+        bytes[5] = self.rotation;
+        bytes[6] = self.high_contrast as u8;
+        // month 0 stands for no birthday
+        let (month, day) = self.birthday.unwrap_or((0, 0));
+        bytes[7] = month;
+        bytes[8] = day;
+        bytes[9] = self.fahrenheit as u8;
+        bytes[10] = self.hour12 as u8;
+        bytes[11] = self.backlight_day_hours.0;
+        bytes[12] = self.backlight_day_hours.1;
+        bytes[13..17].copy_from_slice(&self.location.0.to_le_bytes());
+        bytes[17..21].copy_from_slice(&self.location.1.to_le_bytes());
+        let name = self.timezone.name().as_bytes();
+        bytes[21..21 + name.len()].copy_from_slice(name);
+        bytes[SERIALIZED_LEN - 1] = checksum(&bytes[..SERIALIZED_LEN - 1]);
+        bytes
+    }
+
+    /// Returns `None` when nothing valid was ever written.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Settings> {
+        if bytes.len() < HEADER_LEN || bytes[0] != MAGIC {
+            return None;
+        }
+        let len = bytes[2] as usize;
+        if bytes.len() < HEADER_LEN + len + 1
+            || checksum(&bytes[..HEADER_LEN + len]) != bytes[HEADER_LEN + len]
+        {
+            return None;
+        }
+        let fields = &bytes[HEADER_LEN..HEADER_LEN + len];
+        let defaults = Settings::default();
+        Some(Settings {
+            sky_theme: fields.first().copied().unwrap_or(defaults.sky_theme),
+            countdown_preset: fields.get(1).copied().unwrap_or(defaults.countdown_preset),
+            rotation: fields.get(2).copied().unwrap_or(defaults.rotation),
+            high_contrast: fields
+                .get(3)
+                .map(|b| *b != 0)
+                .unwrap_or(defaults.high_contrast),
+            birthday: match (fields.get(4), fields.get(5)) {
+                (Some(&month), Some(&day)) if month != 0 => Some((month, day)),
+                (Some(_), Some(_)) => None,
+                _ => defaults.birthday,
+            },
+            fahrenheit: fields
+                .get(6)
+                .map(|b| *b != 0)
+                .unwrap_or(defaults.fahrenheit),
+            hour12: fields.get(7).map(|b| *b != 0).unwrap_or(defaults.hour12),
+            backlight_day_hours: match (fields.get(8), fields.get(9)) {
+                (Some(&start), Some(&end)) => (start, end),
+                _ => defaults.backlight_day_hours,
+            },
+            location: match (fields.get(10..14), fields.get(14..18)) {
+                (Some(latitude), Some(longitude)) => (f32_at(latitude), f32_at(longitude)),
+                _ => defaults.location,
+            },
+            timezone: fields
+                .get(18..18 + TIMEZONE_NAME_LEN)
+                .and_then(timezone_named)
+                .unwrap_or(defaults.timezone),
+        })
+    }
+}
+
+fn f32_at(bytes: &[u8]) -> f32 {
+    f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The timezone of a zero padded name, `None` for a name this build of `chrono_tz` doesn't
+/// know.
+fn timezone_named(bytes: &[u8]) -> Option<Tz> {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).ok()?.parse().ok()
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+static CURRENT: CriticalSectionMutex<Cell<Option<Settings>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+static SAVE_REQUEST: Signal<CriticalSectionRawMutex, Settings> = Signal::new();
+
+/// The settings in use, defaults until `init` is called.
+pub fn current() -> Settings {
+    CURRENT.lock(|c| c.get()).unwrap_or_default()
+}
+
+/// Sets the settings loaded at boot, without writing them back.
+pub fn init(settings: Settings) {
+    CURRENT.lock(|c| c.set(Some(settings)));
+}
+
+/// Changes the settings and asks for them to be persisted.
+pub fn update(settings: Settings) {
+    if current() != settings {
+        init(settings);
+        SAVE_REQUEST.signal(settings);
+    }
+}
+
+/// Waits for settings to persist, several updates in a row are coalesced.
+pub async fn wait_for_save() -> Settings {
+    SAVE_REQUEST.wait().await
+}
+
+#[cfg(feature = "mcu")]
+pub use flash::FlashSettingsStore;
+
+#[cfg(feature = "mcu")]
+mod flash {
+    use alloc::{vec, vec::Vec};
+    use embassy_futures::select::{select, Either};
+    use embassy_time::{Duration, Timer};
+    use embedded_storage::{ReadStorage, Storage};
+    use esp_storage::FlashStorage;
+
+    use super::{Settings, SERIALIZED_LEN};
+    use crate::sky::{self, Sky};
+
+    /// Start of the `nvs` partition of the default partition table, unused by this firmware.
+    const SETTINGS_FLASH_OFFSET: u32 = 0x9000;
+    /// Sky table replacing the compiled-in one, further in the `nvs` partition, see
+    /// `sky::parse_sky_table`.
+    const SKY_TABLE_FLASH_OFFSET: u32 = 0xA000;
+    /// Settings are written once they stopped changing for that long, the encoder sends an
+    /// update per detent.
+    const SAVE_DELAY: Duration = Duration::from_secs(3);
+
+    pub struct FlashSettingsStore {
+        flash: FlashStorage,
+    }
+
+    impl FlashSettingsStore {
+        pub fn new() -> Self {
+            Self {
+                flash: FlashStorage::new(),
+            }
+        }
+
+        pub fn load(&mut self) -> Option<Settings> {
+            let mut bytes = [0; SERIALIZED_LEN];
+            match self.flash.read(SETTINGS_FLASH_OFFSET, &mut bytes) {
+                Ok(_) => Settings::from_bytes(&bytes),
+                Err(e) => {
+                    log::error!("could not read settings: {:?}", e);
+                    None
+                }
+            }
+        }
+
+        /// The sky table written to flash, `None` when there is none or it is invalid.
+        pub fn load_sky_table(&mut self) -> Option<&'static [Sky]> {
+            let mut header = [0; sky::SKY_TABLE_HEADER_LEN];
+            if let Err(e) = self.flash.read(SKY_TABLE_FLASH_OFFSET, &mut header) {
+                log::error!("could not read the sky table: {:?}", e);
+                return None;
+            }
+            // erased flash, nothing was ever written
+            let len = sky::sky_table_len(&header).ok()?;
+            let mut bytes = vec![0; len];
+            if let Err(e) = self.flash.read(SKY_TABLE_FLASH_OFFSET, &mut bytes) {
+                log::error!("could not read the sky table: {:?}", e);
+                return None;
+            }
+            match sky::parse_sky_table(&bytes) {
+                Ok(table) => Some(Vec::leak(table)),
+                Err(e) => {
+                    log::error!("invalid sky table, keeping the default one: {:?}", e);
+                    None
+                }
+            }
+        }
+
+        pub fn save(&mut self, settings: &Settings) {
+            if let Err(e) = self.flash.write(SETTINGS_FLASH_OFFSET, &settings.to_bytes()) {
+                log::error!("could not write settings: {:?}", e);
+            }
+        }
+
+        /// Writes the settings `SAVE_DELAY` after they last changed, unless they are back to
+        /// what the flash holds.
+        pub async fn run(mut self) -> ! {
+            let mut saved = self.load().map(|settings| settings.to_bytes());
+            loop {
+                let mut settings = super::wait_for_save().await;
+                while let Either::First(newer) =
+                    select(super::wait_for_save(), Timer::after(SAVE_DELAY)).await
+                {
+                    settings = newer;
+                }
+                let bytes = settings.to_bytes();
+                if saved == Some(bytes) {
+                    continue;
+                }
+                log::info!("saving {:?}", settings);
+                self.save(&settings);
+                saved = Some(bytes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = Settings {
+            sky_theme: 2,
+            countdown_preset: 90,
+            rotation: 3,
+            high_contrast: true,
+            birthday: Some((12, 24)),
+            fahrenheit: true,
+            hour12: true,
+            backlight_day_hours: (7, 22),
+            location: (-33.87, 151.21),
+            timezone: chrono_tz::America::Argentina::ComodRivadavia,
+        };
+        assert_eq!(Settings::from_bytes(&settings.to_bytes()), Some(settings));
+        let defaults = Settings::default();
+        assert_eq!(Settings::from_bytes(&defaults.to_bytes()), Some(defaults));
+    }
+
+    #[test]
+    fn older_layout_keeps_the_new_fields_default() {
+        // version 1: the sky theme and the countdown preset only
+        let mut bytes = [MAGIC, 1, 2, 1, 60, 0];
+        bytes[5] = checksum(&bytes[..5]);
+        let expected = Settings {
+            sky_theme: 1,
+            countdown_preset: 60,
+            ..Settings::default()
+        };
+        assert_eq!(Settings::from_bytes(&bytes), Some(expected));
+    }
+
+    #[test]
+    fn invalid_settings_are_ignored() {
+        // erased flash
+        assert_eq!(Settings::from_bytes(&[0xFF; SERIALIZED_LEN]), None);
+        let mut bytes = Settings::default().to_bytes();
+        bytes[4] ^= 1;
+        assert_eq!(Settings::from_bytes(&bytes), None);
+        assert_eq!(Settings::from_bytes(&bytes[..SERIALIZED_LEN - 1]), None);
+    }
+
+    #[test]
+    fn unknown_timezone_keeps_the_default() {
+        let mut bytes = Settings {
+            timezone: Tz::UTC,
+            ..Settings::default()
+        }
+        .to_bytes();
+        // "UTC" renamed "UTX"
+        bytes[HEADER_LEN + 20] = b'X';
+        bytes[SERIALIZED_LEN - 1] = checksum(&bytes[..SERIALIZED_LEN - 1]);
+        let settings = Settings::from_bytes(&bytes).unwrap();
+        assert_eq!(settings.timezone, Europe::Paris);
+    }
+}
@@ -1,15 +1,18 @@
-use core::fmt::Display;
+use core::{cell::Cell, fmt::Display};
 
 use alloc::vec::{self, Vec};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
 use color_hex::color_from_hex;
-use i_slint_core::graphics::{GradientStop, LinearGradientBrush};
+use i_slint_core::graphics::{GradientStop, LinearGradientBrush, RadialGradientBrush};
 use micromath::F32Ext;
 
 use slint::{Brush, Color};
 use slint_generated::TimeOfDay;
-use spa::FloatOps;
+use spa::{FloatOps, SunriseAndSet};
+
+use crate::moon::Moon;
 
 pub enum MicroMathFloatOps {}
 impl FloatOps for MicroMathFloatOps {
@@ -214,7 +217,7 @@ pub const SKY: [Sky; 22] = [
         },
         moment: SkyMoment::MORNING,
         start_angle: 20.0,
-        end_angle: 80.0,
+        end_angle: 90.0,
     },
     Sky {
         gradient: Gradient {
@@ -223,7 +226,7 @@ pub const SKY: [Sky; 22] = [
             end: color_from_hex_str!("#2B8BBD"),
         },
         moment: SkyMoment::AFTERNOON,
-        start_angle: 80.0,
+        start_angle: 90.0,
         end_angle: 1.5,
     },
     Sky {
@@ -333,87 +336,466 @@ pub const SKY: [Sky; 22] = [
 ];
 
 fn mix_colors(lower: &Color, upper: &Color, mix_factor: f32) -> Color {
-    log::info!("Mixing: {} and {} at {}", lower, upper, mix_factor * 100.0);
+    crate::log_throttled!(
+        1000,
+        log::Level::Info,
+        "Mixing: {} and {} at {}",
+        lower,
+        upper,
+        mix_factor * 100.0
+    );
     upper.mix(lower, mix_factor)
 }
 
+/// Color palettes the sky gradient can be rendered with, see `SKY_THEMES`.
+#[derive(Debug, Clone, Copy)]
+pub enum SkyTheme {
+    DEFAULT,
+    GRAYSCALE,
+    VIVID,
+    PASTEL,
+}
+
+pub const SKY_THEMES: [SkyTheme; 4] = [
+    SkyTheme::DEFAULT,
+    SkyTheme::GRAYSCALE,
+    SkyTheme::VIVID,
+    SkyTheme::PASTEL,
+];
+
+impl SkyTheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkyTheme::DEFAULT => "Default",
+            SkyTheme::GRAYSCALE => "Grayscale",
+            SkyTheme::VIVID => "Vivid",
+            SkyTheme::PASTEL => "Pastel",
+        }
+    }
+
+    fn apply(&self, color: Color) -> Color {
+        let (r, g, b) = (color.red() as f32, color.green() as f32, color.blue() as f32);
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+        match self {
+            SkyTheme::DEFAULT => color,
+            SkyTheme::GRAYSCALE => Color::from_rgb_u8(luma as u8, luma as u8, luma as u8),
+            SkyTheme::VIVID => {
+                // push each channel away from the luma to boost saturation
+                let boost = |c: f32| (luma + (c - luma) * 1.6).clamp(0.0, 255.0) as u8;
+                Color::from_rgb_u8(boost(r), boost(g), boost(b))
+            }
+            SkyTheme::PASTEL => Color::from_rgb_u8(255, 255, 255).mix(&color, 0.4),
+        }
+    }
+}
+
+impl Display for SkyTheme {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "SkyTheme::{}", self.name())
+    }
+}
+
 impl Sky {
+    /// Entries after noon are listed with a setting sun, their `end_angle` is below `start_angle`.
+    fn is_descending(self) -> bool {
+        self.end_angle < self.start_angle
+    }
+
+    /// Unfolds an elevation on an axis that keeps growing through the whole day, so that
+    /// morning and afternoon entries can be interpolated together: the afternoon is mirrored
+    /// around the zenith (80° in the afternoon becomes 100°).
+    fn unfold(self, angle: f32) -> f32 {
+        if self.is_descending() {
+            180.0 - angle
+        } else {
+            angle
+        }
+    }
+
     fn midle_angle(self) -> f32 {
-        self.start_angle + (self.end_angle - self.start_angle) / 2.0
+        (self.unfold(self.start_angle) + self.unfold(self.end_angle)) / 2.0
     }
 }
 
-type NightFactor = f32;
+/// A sky table can replace `SKY` at boot, e.g. written to flash with
+/// `espflash write-bin 0xa000 sky.bin` (see `FlashSettingsStore::load_sky_table`).
+///
+/// Layout of the serialized table:
+///
+/// | byte    | content                                        |
+/// |---------|------------------------------------------------|
+/// | 0       | `SKY_TABLE_MAGIC`                              |
+/// | 1       | `SKY_TABLE_VERSION`                            |
+/// | 2       | `n`, number of entries                         |
+/// | 3..     | entries, `SKY_ENTRY_LEN` bytes each            |
+/// | 3+19n   | checksum, wrapping sum of the previous bytes   |
+///
+/// An entry is the start color (RGB), the step color (RGB) and position, the end color (RGB),
+/// the `SkyMoment` index, then the start and end angles as little endian f32.
+const SKY_TABLE_MAGIC: u8 = 0x5C;
+const SKY_TABLE_VERSION: u8 = 1;
+pub const SKY_TABLE_HEADER_LEN: usize = 3;
+const SKY_ENTRY_LEN: usize = 19;
+/// Longest table accepted, the compiled-in one has 22 entries.
+const SKY_TABLE_MAX_ENTRIES: usize = 48;
 
-pub fn get_slint_gradient(
-    date_time: DateTime<Utc>,
-) -> (TimeOfDay, NightFactor, LinearGradientBrush) {
-    let pos = spa::solar_position::<MicroMathFloatOps>(date_time, 48.866667, 2.333333).unwrap();
+const SKY_MOMENTS: [SkyMoment; 7] = [
+    SkyMoment::NIGHT,
+    SkyMoment::DAWN,
+    SkyMoment::SUNRISE,
+    SkyMoment::MORNING,
+    SkyMoment::AFTERNOON,
+    SkyMoment::SUNSET,
+    SkyMoment::DUSK,
+];
 
-    let angle = 90.0 - pos.zenith_angle as f32; // in degrees
+/// Why a serialized sky table was rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkyTableError {
+    /// Not a sky table, e.g. erased flash.
+    BadHeader,
+    Truncated,
+    BadChecksum,
+    TooManyEntries,
+    UnknownMoment(u8),
+    /// The angles can't be looked up like `SKY`: see `validate_sky_table`.
+    BadAngles,
+}
 
-    let current_idx = if (pos.azimuth > 180.0) {
-        SKY.iter()
-            .rev()
-            .position(|s| {
-                log::info!("testing : {} with {}", s, angle);
-                s.start_angle > (angle as f32)
+/// Full size of the table starting with `header`, to read it in one go.
+pub fn sky_table_len(header: &[u8]) -> Result<usize, SkyTableError> {
+    match header {
+        [SKY_TABLE_MAGIC, SKY_TABLE_VERSION, count, ..] => {
+            Ok(SKY_TABLE_HEADER_LEN + *count as usize * SKY_ENTRY_LEN + 1)
+        }
+        _ => Err(SkyTableError::BadHeader),
+    }
+}
+
+/// Deserializes and validates a sky table.
+pub fn parse_sky_table(bytes: &[u8]) -> Result<Vec<Sky>, SkyTableError> {
+    let len = sky_table_len(bytes)?;
+    if bytes.len() < len {
+        return Err(SkyTableError::Truncated);
+    }
+    let checksum = bytes[..len - 1].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    if checksum != bytes[len - 1] {
+        return Err(SkyTableError::BadChecksum);
+    }
+    if bytes[2] as usize > SKY_TABLE_MAX_ENTRIES {
+        return Err(SkyTableError::TooManyEntries);
+    }
+
+    let color = |b: &[u8]| Color::from_rgb_u8(b[0], b[1], b[2]);
+    let angle = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    let table = bytes[SKY_TABLE_HEADER_LEN..len - 1]
+        .chunks_exact(SKY_ENTRY_LEN)
+        .map(|e| {
+            Ok(Sky {
+                gradient: Gradient {
+                    start: color(&e[0..3]),
+                    steps: [(color(&e[3..6]), e[6])],
+                    end: color(&e[7..10]),
+                },
+                moment: *SKY_MOMENTS
+                    .get(e[10] as usize)
+                    .ok_or(SkyTableError::UnknownMoment(e[10]))?,
+                start_angle: angle(&e[11..15]),
+                end_angle: angle(&e[15..19]),
             })
-            .unwrap()
-    } else {
-        SKY.iter()
-            .position(|s| s.start_angle > (angle as f32))
-            .unwrap()
+        })
+        .collect::<Result<Vec<Sky>, SkyTableError>>()?;
+
+    if !validate_sky_table(&table) {
+        return Err(SkyTableError::BadAngles);
+    }
+    Ok(table)
+}
+
+/// Checks a table is laid out like `SKY`, which `get_slint_gradient` relies on: rising entries
+/// from the bottom of the night, with growing start angles, then setting entries with
+/// shrinking start angles back to the bottom of the night.
+///
+/// Any sun elevation must be found in both halves of the day: the first setting entry starts
+/// at the zenith, and the sun at its lowest (-90°) is in the second half of the first entry and
+/// the first half of the last one, so it is blended with the entry next to it.
+fn validate_sky_table(table: &[Sky]) -> bool {
+    let (Some(first), Some(last)) = (table.first(), table.last()) else {
+        return false;
     };
+    let rising = table.iter().take_while(|s| !s.is_descending()).count();
+    let (morning, afternoon) = table.split_at(rising);
+    morning.len() >= 2
+        && afternoon.len() >= 1
+        && afternoon.iter().all(|s| s.is_descending())
+        && table.iter().all(|s| s.start_angle.is_finite() && s.end_angle.is_finite())
+        && afternoon[0].start_angle >= 90.0
+        && first.midle_angle() <= -90.0
+        && last.midle_angle() >= last.unfold(-90.0)
+        && morning.windows(2).all(|w| w[0].start_angle < w[1].start_angle)
+        && afternoon.windows(2).all(|w| w[0].start_angle > w[1].start_angle)
+}
+
+static ACTIVE_SKY: CriticalSectionMutex<Cell<&'static [Sky]>> =
+    CriticalSectionMutex::new(Cell::new(&SKY));
 
-    let idx = if (pos.azimuth > 180.0) {
-        SKY.len() - 1 - current_idx as usize
+/// Replaces the compiled-in `SKY` table, for the next computed skies.
+pub fn set_sky_table(table: &'static [Sky]) {
+    ACTIVE_SKY.lock(|s| s.set(table));
+}
+
+/// The sky table in use, `SKY` unless `set_sky_table` replaced it.
+pub fn active_sky() -> &'static [Sky] {
+    ACTIVE_SKY.lock(|s| s.get())
+}
+
+/// Where the clock is by default, in degrees (Paris).
+pub const LATITUDE: f64 = 48.866667;
+pub const LONGITUDE: f64 = 2.333333;
+
+/// Where the clock is, as (latitude, longitude) in degrees, see `Settings::location`.
+pub fn location() -> (f64, f64) {
+    let (latitude, longitude) = crate::settings::current().location;
+    (latitude as f64, longitude as f64)
+}
+
+type NightFactor = f32;
+
+/// Sun elevation, in degrees, above which the night factor is 0.0.
+pub const DAY_ELEVATION: f32 = 0.5;
+/// Sun elevation, in degrees, below which the night factor is 1.0.
+pub const NIGHT_ELEVATION: f32 = -8.0;
+
+/// Entries of `sky` for a sun `angle`, in the morning or the afternoon half of the day: the
+/// index of the entry it is in, that entry, and the upper and lower entries to blend, as
+/// `(idx, current, upper, lower)`. `None` when the table doesn't cover the angle.
+fn lookup_sky(sky: &[Sky], angle: f32, afternoon: bool) -> Option<(usize, Sky, Sky, Sky)> {
+    let idx = if afternoon {
+        let from_end = sky.iter().rev().position(|s| s.start_angle > angle)?;
+        sky.len() - 1 - from_end
     } else {
-        current_idx as usize - 1
+        sky.iter().position(|s| s.start_angle > angle)?.checked_sub(1)?
     };
-    log::info!(
-        "date: {}, angle: {}, currentidx: {}, idx: {}, pos: {}",
-        date_time,
+    let current = *sky.get(idx)?;
+
+    let unfolded_angle = current.unfold(angle);
+    let position_in_interval = (unfolded_angle - current.unfold(current.start_angle))
+        / (current.unfold(current.end_angle) - current.unfold(current.start_angle));
+    crate::log_throttled!(
+        1000,
+        log::Level::Info,
+        "angle: {}, idx: {}, position_in_interval: {}",
         angle,
-        current_idx,
         idx,
-        pos.azimuth
+        position_in_interval
     );
 
-    let current_sky = SKY[idx];
+    let (upper, lower) = if position_in_interval < 0.5 {
+        (current, *sky.get(idx.checked_sub(1)?)?)
+    } else {
+        (*sky.get(idx + 1)?, current)
+    };
+    Some((idx, current, upper, lower))
+}
+
+/// Where the sun `angle` is from the middle of `lower` (0.0) to the middle of `upper` (1.0), as
+/// found by `lookup_sky` with `current`.
+fn blend_position(angle: f32, current: Sky, upper: Sky, lower: Sky) -> f32 {
+    // the entry was picked for the current half of the day, so it unfolds the angle consistently
+    let unfolded_angle = current.unfold(angle);
+    (unfolded_angle - lower.midle_angle()) / (upper.midle_angle() - lower.midle_angle())
+}
+
+/// Darkness of the sky for a sun elevation in degrees: 0.0 from `DAY_ELEVATION` up, 1.0 from
+/// `NIGHT_ELEVATION` down and linear in between. Exactly saturated at both ends, the monster
+/// only goes to sleep once it reaches 1.0.
+pub fn night_factor(elevation: f32) -> NightFactor {
+    ((elevation - DAY_ELEVATION) / (NIGHT_ELEVATION - DAY_ELEVATION)).clamp(0.0, 1.0)
+}
 
-    let position_in_interval =
-        (angle - current_sky.start_angle) / (current_sky.end_angle - current_sky.start_angle);
+/// How the sky gradient is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkyGradientStyle {
+    /// Horizon to zenith gradient, rotated toward the sun.
+    LINEAR,
+    /// Circular gradient around the sun, looks more natural on a round panel at sunrise/sunset.
+    RADIAL,
+}
 
-    let (upper_sky, lower_sky) = if (position_in_interval < 0.5) {
-        log::info!(
-            "angle: {}, position_in_interval: {}, upper_idx: {}, lower_idx: {}",
-            angle,
-            position_in_interval,
-            idx,
-            idx - 1
-        );
-        (SKY[idx], SKY[idx - 1])
+pub const SKY_GRADIENT_STYLE: SkyGradientStyle = SkyGradientStyle::LINEAR;
+
+/// How the colors of two `SKY` entries are blended in between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkyEasing {
+    /// Constant pace from one entry to the next.
+    LINEAR,
+    /// Lingers on each entry and speeds up in between, softer at sunrise and sunset.
+    SMOOTHSTEP,
+}
+
+pub const SKY_EASING: SkyEasing = SkyEasing::SMOOTHSTEP;
+
+impl SkyEasing {
+    /// Eases a blend factor from 0.0 to 1.0, both ends and the midpoint stay in place.
+    pub fn apply(self, factor: f32) -> f32 {
+        match self {
+            SkyEasing::LINEAR => factor,
+            SkyEasing::SMOOTHSTEP => factor * factor * (3.0 - 2.0 * factor),
+        }
+    }
+}
+
+/// Position of the sun on screen, from 0.0 (left/top) to 1.0 (right/bottom).
+/// The sun rises on the left at the horizon (bottom) and sets on the right.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunPosition {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Colors at both ends of the sky gradient, themed, e.g. to light LEDs the same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyColors {
+    /// Horizon side of the gradient.
+    pub start: Color,
+    pub end: Color,
+}
+
+/// Rotation of the linear sky gradient, in degrees, for a sun azimuth in degrees.
+///
+/// It follows the azimuth: the gradient runs left to right at sunrise (east, 90°), top to
+/// bottom at noon (south) and right to left at sunset (west, 270°). Picking a formula per half
+/// of the day from the elevation flipped it by 180° when the sun crossed south, and again at
+/// midnight. 360° and 0° being the same rotation, it is continuous all day long.
+pub fn gradient_rotation(azimuth: f32) -> f32 {
+    let rotation = azimuth % 360.0;
+    if rotation < 0.0 {
+        rotation + 360.0
     } else {
-        log::info!(
-            "angle: {}, position_in_interval: {}, upper_idx: {}, lower_idx: {}",
-            angle,
-            position_in_interval,
-            idx + 1,
-            idx
-        );
-        (SKY[idx + 1], SKY[idx])
-    };
-    log::info!(
-        "angle: {}, position_in_interval: {}, upper.middle: {}, lower.middle: {}",
+        rotation
+    }
+}
+
+/// Sun used to draw the sky when `spa` can't place it: high in the morning, a plain day sky.
+///
+/// `spa` only rejects coordinates out of range, which a location set out of range is, so the
+/// clock keeps running rather than reboot on it.
+const FALLBACK_SOLAR_POSITION: spa::SolarPos = spa::SolarPos {
+    zenith_angle: 45.0,
+    azimuth: 150.0,
+};
+
+/// Position of the sun seen from (`latitude`, `longitude`), `FALLBACK_SOLAR_POSITION` when
+/// `spa` rejects them.
+fn solar_position(date_time: DateTime<Utc>, latitude: f64, longitude: f64) -> spa::SolarPos {
+    match spa::solar_position::<MicroMathFloatOps>(date_time, latitude, longitude) {
+        Ok(pos) => pos,
+        Err(e) => {
+            crate::log_throttled!(
+                60_000,
+                log::Level::Error,
+                "could not compute the sun position at {}: {:?}",
+                date_time,
+                e
+            );
+            FALLBACK_SOLAR_POSITION
+        }
+    }
+}
+
+/// What lights the sky at night.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NightSky {
+    /// The dark gradients of the `SKY` night entries.
+    DARK,
+    /// Tinted blue by the moon while it is up, the more so the fuller and higher it is, and
+    /// turned toward it once the night is complete.
+    MOONLIT,
+}
+
+/// Colors of a sky fully lit by the moon, at both ends of the gradient.
+const MOONLIGHT_HORIZON: Color = color_from_hex_str!("#3A4D73");
+const MOONLIGHT_ZENITH: Color = color_from_hex_str!("#1B2A4A");
+/// Share of the moonlight colors in the sky under a full moon high in a dark sky.
+const MOONLIGHT_MAX_MIX: f32 = 0.7;
+/// Moon altitude, in degrees, above which it lights the sky fully.
+const MOONLIGHT_FULL_ALTITUDE: f32 = 30.0;
+
+/// The moon as it lights the sky, see `NightSky::MOONLIT`.
+struct Moonlight {
+    /// Share of the moonlight colors, from 0.0 to `MOONLIGHT_MAX_MIX`.
+    strength: f32,
+    /// Azimuth of the moon, in degrees.
+    azimuth: f32,
+}
+
+/// How much the moon lights the sky at `date_time`, `None` while it is below the horizon.
+fn moonlight(date_time: DateTime<Utc>, night_factor: NightFactor) -> Option<Moonlight> {
+    let moon = Moon::new(date_time);
+    let (latitude, longitude) = location();
+    let (altitude, azimuth) =
+        moon.horizontal_position(date_time, latitude as f32, longitude as f32);
+    if altitude <= 0.0 {
+        return None;
+    }
+    let height = (altitude / MOONLIGHT_FULL_ALTITUDE).min(1.0);
+    Some(Moonlight {
+        strength: MOONLIGHT_MAX_MIX * night_factor * moon.illumination * height,
+        azimuth,
+    })
+}
+
+/// Computes the sky for the given time.
+///
+/// This is a pure function of `date_time`, `theme`, `night_sky` and the location (logging
+/// aside): the controller caches its result for a minute, which is only correct as long as two
+/// calls with the same input return equal values. Keep it free of other global state, the sky
+/// table is only replaced at boot, before the first sky is computed. A new location shows with
+/// the next minute.
+pub fn get_slint_gradient(
+    date_time: DateTime<Utc>,
+    theme: SkyTheme,
+    night_sky: NightSky,
+) -> (TimeOfDay, NightFactor, Brush, SunPosition, SkyColors) {
+    let (latitude, longitude) = location();
+    let pos = solar_position(date_time, latitude, longitude);
+    let angle = 90.0 - pos.zenith_angle as f32; // in degrees
+    let afternoon = pos.azimuth > 180.0;
+    let (idx, current_sky, upper_sky, lower_sky) = lookup_sky(active_sky(), angle, afternoon)
+        .or_else(|| {
+            crate::log_throttled!(
+                60_000,
+                log::Level::Error,
+                "the sky table doesn't cover the sun at {}, using the default one",
+                angle
+            );
+            lookup_sky(&SKY, angle, afternoon)
+        })
+        .unwrap_or_else(|| {
+            let fallback = 90.0 - FALLBACK_SOLAR_POSITION.zenith_angle as f32;
+            lookup_sky(&SKY, fallback, false).expect("SKY covers the fallback sun")
+        });
+    crate::log_throttled!(
+        1000,
+        log::Level::Info,
+        "date: {}, angle: {}, idx: {}, pos: {}",
+        date_time,
+        angle,
+        idx,
+        pos.azimuth
+    );
+    crate::log_throttled!(
+        1000,
+        log::Level::Info,
+        "angle: {}, upper.middle: {}, lower.middle: {}",
         angle,
-        position_in_interval,
         upper_sky.midle_angle(),
         lower_sky.midle_angle()
     );
 
-    let mix_factor =
-        (angle - lower_sky.midle_angle()) / (upper_sky.midle_angle() - lower_sky.midle_angle());
+    let mix_factor = SKY_EASING.apply(
+        blend_position(angle, current_sky, upper_sky, lower_sky).clamp(0.0, 1.0),
+    );
 
     let start_color = mix_colors(
         &lower_sky.gradient.start,
@@ -423,7 +805,24 @@ pub fn get_slint_gradient(
 
     let end_color = mix_colors(&lower_sky.gradient.end, &upper_sky.gradient.end, mix_factor);
 
-    log::info!(
+    let night_factor = night_factor(angle);
+    let moonlight = match night_sky {
+        NightSky::MOONLIT => moonlight(date_time, night_factor),
+        NightSky::DARK => None,
+    };
+    let (start_color, end_color) = match &moonlight {
+        Some(light) => (
+            MOONLIGHT_HORIZON.mix(&start_color, light.strength),
+            MOONLIGHT_ZENITH.mix(&end_color, light.strength),
+        ),
+        None => (start_color, end_color),
+    };
+
+    let (start_color, end_color) = (theme.apply(start_color), theme.apply(end_color));
+
+    crate::log_throttled!(
+        1000,
+        log::Level::Info,
         "angle: {}, currentidx: {}, mix_factor: {}, sky: {}",
         angle,
         idx,
@@ -441,26 +840,30 @@ pub fn get_slint_gradient(
         SkyMoment::DUSK => TimeOfDay::TWILIGHT,
     };
 
-    let corrected_angle = if (pos.azimuth > 180.0) {
-        270.0 + angle
-    } else {
-        90.0 + angle
+    let corrected_angle = match moonlight {
+        // the sun still lights the sky during the twilight
+        Some(light) if night_factor >= 1.0 => gradient_rotation(light.azimuth),
+        _ => gradient_rotation(pos.azimuth as f32),
     };
-
-    let night_factor = ((angle - (0.5)) / (-8.0 - 0.5)).clamp(0.0, 1.0);
-    log::info!(
-        "corrected angle: {}, night_factor: {}, tod: {}, start: {}, end: {}",
+    crate::log_throttled!(
+        1000,
+        log::Level::Info,
+        "corrected angle: {}, night_factor: {}, tod: {}, theme: {}, start: {}, end: {}",
         corrected_angle,
         night_factor * 100.0,
         current_sky.moment,
+        theme,
         start_color,
         end_color
     );
 
-    (
-        tod,
-        night_factor,
-        LinearGradientBrush::new(
+    let sun = SunPosition {
+        x: ((pos.azimuth as f32 - 90.0) / 180.0).clamp(0.0, 1.0),
+        y: (1.0 - angle / 90.0).clamp(0.0, 1.0),
+    };
+
+    let brush = match SKY_GRADIENT_STYLE {
+        SkyGradientStyle::LINEAR => Brush::LinearGradient(LinearGradientBrush::new(
             corrected_angle,
             [
                 GradientStop {
@@ -472,6 +875,257 @@ pub fn get_slint_gradient(
                     position: 0.7,
                 },
             ],
-        ),
-    )
+        )),
+        // slint radial gradients are always centered, the UI moves the sky so its center
+        // lands on the sun
+        SkyGradientStyle::RADIAL => Brush::RadialGradient(RadialGradientBrush::new_circle([
+            GradientStop {
+                color: start_color,
+                position: 0.0,
+            },
+            GradientStop {
+                color: end_color,
+                position: 0.7,
+            },
+        ])),
+    };
+
+    let colors = SkyColors {
+        start: start_color,
+        end: end_color,
+    };
+
+    (tod, night_factor, brush, sun, colors)
+}
+
+/// Next sunrise or sunset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunEvent {
+    pub at: DateTime<Utc>,
+    /// False for a sunset.
+    pub sunrise: bool,
+}
+
+/// First sunrise or sunset after `date_time`, `None` during a polar day or night.
+pub fn next_sun_event(date_time: DateTime<Utc>) -> Option<SunEvent> {
+    let (latitude, longitude) = location();
+    // today's events may be past already, tomorrow's sunrise is then the next one
+    (0..2).find_map(|days| {
+        let day = date_time + TimeDelta::days(days);
+        match spa::sunrise_and_set::<MicroMathFloatOps>(day, latitude, longitude) {
+            Ok(SunriseAndSet::Daylight(sunrise, sunset)) => [(sunrise, true), (sunset, false)]
+                .into_iter()
+                .find(|(at, _)| *at > date_time)
+                .map(|(at, sunrise)| SunEvent { at, sunrise }),
+            Ok(_) => None,
+            Err(e) => {
+                log::error!("could not compute sun events: {:?}", e);
+                None
+            }
+        }
+    })
+}
+
+/// Height of the horizon line the sun arc stands on, as a fraction of the screen height.
+pub const SUN_ARC_HORIZON: f32 = 0.6;
+/// Radius of the sun arc, as a fraction of the screen size.
+pub const SUN_ARC_RADIUS: f32 = 0.4;
+
+/// Position of the sun on its day arc: a half circle over the horizon line, from the left end
+/// at sunrise to the right end at sunset, its top at solar noon.
+///
+/// Below the horizon the sun stays on the horizon line, at the end where it will rise or has
+/// set. `None` during a polar day or night, there is no arc then.
+pub fn sun_arc_position(date_time: DateTime<Utc>) -> Option<SunPosition> {
+    let (latitude, longitude) = location();
+    match spa::sunrise_and_set::<MicroMathFloatOps>(date_time, latitude, longitude) {
+        Ok(SunriseAndSet::Daylight(sunrise, sunset)) => {
+            let progress = ((date_time - sunrise).num_seconds() as f32
+                / (sunset - sunrise).num_seconds() as f32)
+                .clamp(0.0, 1.0);
+            let angle = progress * core::f32::consts::PI;
+            Some(SunPosition {
+                x: 0.5 - SUN_ARC_RADIUS * angle.cos(),
+                y: SUN_ARC_HORIZON - SUN_ARC_RADIUS * angle.sin(),
+            })
+        }
+        Ok(_) => None,
+        Err(e) => {
+            log::error!("could not compute the sun arc: {:?}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn same_time_same_sky() {
+        // night, sunrise, noon, sunset and dusk in Paris
+        for (hour, min) in [(1, 0), (4, 0), (11, 50), (19, 58), (20, 40)] {
+            let time = utc(2024, 6, 21, hour, min);
+            for theme in SKY_THEMES {
+                for night_sky in [NightSky::DARK, NightSky::MOONLIT] {
+                    assert_eq!(
+                        get_slint_gradient(time, theme, night_sky),
+                        get_slint_gradient(time, theme, night_sky),
+                        "{} {} {:?}",
+                        time,
+                        theme,
+                        night_sky
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blend_position_stays_in_range_every_degree() {
+        for afternoon in [false, true] {
+            for angle in -90..90 {
+                let angle = angle as f32;
+                let (_, current, upper, lower) = lookup_sky(&SKY, angle, afternoon).unwrap();
+                let position = blend_position(angle, current, upper, lower);
+                assert!(
+                    (0.0..=1.0).contains(&position),
+                    "{} at {}, afternoon: {}, in {}",
+                    position,
+                    angle,
+                    afternoon,
+                    current
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn night_factor_saturates_at_midnight_and_noon() {
+        let factor = |hour, min| {
+            get_slint_gradient(utc(2024, 3, 20, hour, min), SKY_THEMES[0], NightSky::DARK).1
+        };
+        assert_eq!(factor(0, 0), 1.0);
+        assert_eq!(factor(12, 0), 0.0);
+        // the sun sets around 18:00 UTC, and is 8° below the horizon 50 minutes later
+        let twilight = factor(18, 22);
+        assert!((0.4..0.6).contains(&twilight), "{}", twilight);
+
+        assert_eq!(night_factor(NIGHT_ELEVATION), 1.0);
+        assert_eq!(night_factor(DAY_ELEVATION), 0.0);
+        assert_eq!(night_factor((NIGHT_ELEVATION + DAY_ELEVATION) / 2.0), 0.5);
+    }
+
+    #[test]
+    fn sun_out_of_range_falls_back() {
+        let time = utc(2024, 6, 21, 0, 0);
+        let pos = solar_position(time, 91.0, LONGITUDE);
+        assert_eq!(pos.zenith_angle, FALLBACK_SOLAR_POSITION.zenith_angle);
+        assert_eq!(pos.azimuth, FALLBACK_SOLAR_POSITION.azimuth);
+        // midnight in Paris is not the fallback day sky
+        let pos = solar_position(time, LATITUDE, LONGITUDE);
+        assert!(pos.zenith_angle > 90.0);
+    }
+
+    #[test]
+    fn gradient_rotation_is_continuous() {
+        let distance = |a: f32, b: f32| {
+            let d = (a - b).abs() % 360.0;
+            d.min(360.0 - d)
+        };
+        let mut previous = gradient_rotation(-360.0);
+        for tenth in -3599..=7200 {
+            let rotation = gradient_rotation(tenth as f32 / 10.0);
+            assert!((0.0..360.0).contains(&rotation), "{}", rotation);
+            assert!(distance(rotation, previous) < 0.11, "{} after {}", rotation, previous);
+            previous = rotation;
+        }
+
+        // and through a whole day
+        let angle = |time| match get_slint_gradient(time, SKY_THEMES[0], NightSky::DARK).2 {
+            Brush::LinearGradient(gradient) => gradient.angle(),
+            _ => 0.0,
+        };
+        let midnight = utc(2024, 6, 21, 0, 0);
+        let mut previous = angle(midnight);
+        for minute in 1..24 * 60 {
+            let time = midnight + TimeDelta::minutes(minute);
+            let rotation = angle(time);
+            assert!(distance(rotation, previous) < 2.0, "{} at {}", rotation, time);
+            previous = rotation;
+        }
+    }
+
+    #[test]
+    fn easing_curves_the_blend() {
+        for easing in [SkyEasing::LINEAR, SkyEasing::SMOOTHSTEP] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(0.5), 0.5);
+            assert_eq!(easing.apply(1.0), 1.0);
+        }
+        assert_eq!(SkyEasing::LINEAR.apply(0.25), 0.25);
+        // lingers on the lower entry, catches up on the way to the upper one
+        assert_eq!(SkyEasing::SMOOTHSTEP.apply(0.25), 0.15625);
+        assert_eq!(SkyEasing::SMOOTHSTEP.apply(0.75), 0.84375);
+    }
+
+    /// `table` laid out as `parse_sky_table` reads it.
+    fn serialize(table: &[Sky]) -> Vec<u8> {
+        let mut bytes = alloc::vec![SKY_TABLE_MAGIC, SKY_TABLE_VERSION, table.len() as u8];
+        let rgb = |c: Color| [c.red(), c.green(), c.blue()];
+        for sky in table {
+            let (step, step_position) = sky.gradient.steps[0];
+            bytes.extend(rgb(sky.gradient.start));
+            bytes.extend(rgb(step));
+            bytes.push(step_position);
+            bytes.extend(rgb(sky.gradient.end));
+            bytes.push(sky.moment as u8);
+            bytes.extend(sky.start_angle.to_le_bytes());
+            bytes.extend(sky.end_angle.to_le_bytes());
+        }
+        bytes.push(checksum(&bytes));
+        bytes
+    }
+
+    fn checksum(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+    }
+
+    #[test]
+    fn sky_table_round_trip() {
+        assert!(validate_sky_table(&SKY));
+        let bytes = serialize(&SKY);
+        assert_eq!(sky_table_len(&bytes), Ok(bytes.len()));
+        let table = parse_sky_table(&bytes).unwrap();
+        assert_eq!(serialize(&table), bytes);
+    }
+
+    #[test]
+    fn broken_sky_tables_are_rejected() {
+        let bytes = serialize(&SKY);
+        let error = |bytes: &[u8]| parse_sky_table(bytes).err();
+        assert_eq!(error(&[0xFF; 64]), Some(SkyTableError::BadHeader));
+        assert_eq!(error(&bytes[..bytes.len() - 1]), Some(SkyTableError::Truncated));
+        let mut corrupted = bytes.clone();
+        corrupted[5] ^= 1;
+        assert_eq!(error(&corrupted), Some(SkyTableError::BadChecksum));
+        let mut unknown_moment = bytes.clone();
+        unknown_moment[SKY_TABLE_HEADER_LEN + 10] = SKY_MOMENTS.len() as u8;
+        let last = unknown_moment.len() - 1;
+        unknown_moment[last] = checksum(&unknown_moment[..last]);
+        assert_eq!(
+            error(&unknown_moment),
+            Some(SkyTableError::UnknownMoment(SKY_MOMENTS.len() as u8))
+        );
+        // morning entries out of order
+        let mut swapped = SKY;
+        swapped.swap(1, 2);
+        assert_eq!(error(&serialize(&swapped)), Some(SkyTableError::BadAngles));
+        assert_eq!(error(&serialize(&[])), Some(SkyTableError::BadAngles));
+    }
 }
@@ -28,3 +28,39 @@ impl slint::platform::Platform for EspEmbassyBackend {
         log::debug!("{}", arguments);
     }
 }
+
+/// Notices the frame where the window animations stop.
+///
+/// Animated properties are evaluated while rendering: the last tick of an animation sets its
+/// end value during the draw, after the window was marked clean, so that end state is not
+/// drawn. The render loop draws once more when `stopped` says so, nothing else would wake it
+/// up for it.
+#[derive(Default)]
+pub struct AnimationTracker {
+    animating: bool,
+}
+
+impl AnimationTracker {
+    /// Records whether the frame just drawn had animations running, returns true on the first
+    /// frame without any after frames with some.
+    pub fn stopped(&mut self, animating: bool) -> bool {
+        let stopped = self.animating && !animating;
+        self.animating = animating;
+        stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn animation_stops_once() {
+        let mut tracker = AnimationTracker::default();
+        let stopped: Vec<bool> = [false, true, true, false, false, true, false]
+            .into_iter()
+            .map(|animating| tracker.stopped(animating))
+            .collect();
+        assert_eq!(stopped, [false, false, false, true, false, false, true]);
+    }
+}
@@ -0,0 +1,493 @@
+//! Background tasks of the clock, spawned by the firmware through `ClockTasks`. The render loop
+//! and the inputs stay in the mcu binary, they own their peripherals.
+
+use alloc::{rc::Rc, vec};
+use core::cell::Cell;
+
+use chrono::Timelike;
+use embassy_executor::Spawner;
+use embassy_futures::select::select3;
+use embassy_net::Stack;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, CriticalSectionMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use esp_hal::{
+    ledc::{channel::ChannelHW, timer},
+    usb_serial_jtag::UsbSerialJtagRx,
+    Async,
+};
+use micromath::F32Ext;
+
+use crate::board::types::{LedChannel, RTCUtils};
+use crate::cli::{self, Command};
+use crate::controller::{self, Action, WallClock};
+use crate::cpu_clock;
+#[cfg(not(feature = "offline"))]
+use crate::ntp::{self, await_now, NtpClient};
+use crate::settings;
+#[cfg(feature = "rtc-32khz")]
+use crate::timebase;
+
+#[cfg(feature = "rtc-32khz")]
+const RTC_RESYNC_SECS: u64 = 60;
+
+/// The RTC is only rewritten from NTP when it is off by that much, it counts whole seconds.
+#[cfg(not(feature = "offline"))]
+const RTC_MAX_DRIFT_SECS: i64 = 2;
+/// Writes to the RTC are at least that far apart, whatever the NTP sync rate.
+#[cfg(not(feature = "offline"))]
+const RTC_MIN_WRITE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Delay between the heavy tasks at boot, so their current peaks don't add up and brown out a
+/// weak USB supply. `None` starts everything at once.
+pub const BOOT_STAGGER: Option<Duration> = None;
+
+/// The tasks to run. A variant firmware picks the ones it needs instead of editing `main`:
+///
+/// ```ignore
+/// ClockTasks::default().timer().temperature().spawn(&spawner, rtc).await;
+/// ```
+#[derive(Default)]
+pub struct ClockTasks {
+    backlight: Option<LedChannel>,
+    #[cfg(not(feature = "offline"))]
+    ntp: Option<(NtpClient<'static>, Stack<'static>)>,
+    /// The stack is `None` without WiFi.
+    status: Option<Option<Stack<'static>>>,
+    temperature: bool,
+    timer: bool,
+    serial_cli: Option<UsbSerialJtagRx<'static, Async>>,
+}
+
+impl ClockTasks {
+    /// Backlight level following the sky, see `fade_screen`.
+    pub fn backlight(mut self, bl: LedChannel) -> Self {
+        self.backlight = Some(bl);
+        self
+    }
+
+    /// NTP client, the RTC set from it and the WiFi indicator.
+    #[cfg(not(feature = "offline"))]
+    pub fn ntp(mut self, client: NtpClient<'static>, stack: Stack<'static>) -> Self {
+        self.ntp = Some((client, stack));
+        self
+    }
+
+    /// Data of the status page.
+    pub fn status(mut self, stack: Option<Stack<'static>>) -> Self {
+        self.status = Some(stack);
+        self
+    }
+
+    pub fn temperature(mut self) -> Self {
+        self.temperature = true;
+        self
+    }
+
+    /// Sends the time to the controller every second, the clock is frozen without it.
+    pub fn timer(mut self) -> Self {
+        self.timer = true;
+        self
+    }
+
+    /// Commands from the serial console, see `cli`.
+    pub fn serial_cli(mut self, serial_rx: UsbSerialJtagRx<'static, Async>) -> Self {
+        self.serial_cli = Some(serial_rx);
+        self
+    }
+
+    /// Spawns the tasks, the heavy ones `BOOT_STAGGER` apart.
+    pub async fn spawn(self, spawner: &Spawner, rtc: Rc<RTCUtils>) {
+        if let Some(bl) = self.backlight {
+            let _ = spawner.spawn(fade_screen(bl, rtc.clone()));
+            boot_stagger().await;
+        }
+        #[cfg(not(feature = "offline"))]
+        if let Some((ntp_client, stack)) = self.ntp {
+            let _ = spawner.spawn(run_ntp_client(ntp_client));
+            let _ = spawner.spawn(update_rtc_with_ntp(rtc.clone()));
+            let _ = spawner.spawn(wifi_status_task(stack));
+            boot_stagger().await;
+        }
+        if let Some(stack) = self.status {
+            let _ = spawner.spawn(system_status_task(rtc.clone(), stack));
+        }
+        if self.temperature {
+            let _ = spawner.spawn(temperature_task(rtc.clone()));
+        }
+        if self.timer {
+            let _ = spawner.spawn(update_timer(rtc.clone()));
+        }
+        if let Some(serial_rx) = self.serial_cli {
+            let _ = spawner.spawn(serial_cli(serial_rx));
+        }
+    }
+}
+
+/// Gives the previous heavy task (display, WiFi, backlight...) time to settle before the
+/// next one starts, when `BOOT_STAGGER` is set.
+pub async fn boot_stagger() {
+    if let Some(delay) = BOOT_STAGGER {
+        Timer::after(delay).await;
+    }
+}
+
+/// What drives the backlight level.
+#[derive(PartialEq, Clone, Copy)]
+enum BacklightMode {
+    /// Schedule on the local hour, see `Settings::backlight_day_hours`.
+    Hours,
+    /// Follows the darkness of the sky (`night_factor`), so it tracks sunset across seasons.
+    #[allow(dead_code)]
+    Twilight,
+}
+
+/// `Hours` keeps the fade the clock always had, `Twilight` is opt-in.
+const BACKLIGHT_MODE: BacklightMode = BacklightMode::Hours;
+/// Backlight brightness, in perceived percent, see `backlight_duty`.
+const BACKLIGHT_DAY: u8 = 100;
+const BACKLIGHT_NIGHT: u8 = 25;
+/// Resolution of the backlight PWM, fine enough for the dim steps of `backlight_duty`.
+pub const BACKLIGHT_DUTY_RESOLUTION: timer::config::Duty = timer::config::Duty::Duty10Bit;
+/// Highest duty at `BACKLIGHT_DUTY_RESOLUTION`.
+const BACKLIGHT_DUTY_MAX: u32 = (1 << 10) - 1;
+/// Exponent from perceived brightness to duty, 1.0 for a linear mapping. The eye sees a 5%
+/// duty as about a quarter of the full brightness.
+const BACKLIGHT_GAMMA: f32 = 2.2;
+
+/// Duty of the backlight PWM for a perceived brightness in percent: evenly spaced levels look
+/// evenly spaced, instead of the jumps of a linear duty at the low end. Any non zero level
+/// keeps the backlight on.
+fn backlight_duty(level: u8) -> u32 {
+    if level == 0 {
+        return 0;
+    }
+    let linear = (level.min(100) as f32 / 100.0).powf(BACKLIGHT_GAMMA);
+    ((linear * BACKLIGHT_DUTY_MAX as f32) as u32).max(1)
+}
+/// After a button press the backlight stays at `BACKLIGHT_DAY` that long, whatever the
+/// schedule, then goes back to it. `None` to always follow the schedule.
+const KEEP_AWAKE: Option<Duration> = Some(Duration::from_secs(60));
+
+/// Last button press, see `KEEP_AWAKE`.
+static LAST_INTERACTION: CriticalSectionMutex<Cell<Option<Instant>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+/// Records a button press, brightening the screen right away during `KEEP_AWAKE`.
+pub fn record_interaction() {
+    LAST_INTERACTION.lock(|i| i.set(Some(Instant::now())));
+    BACKLIGHT_OVERRIDE_CHANGED.signal(());
+}
+
+/// Time left in the keep-awake window, `None` when it is over.
+fn keep_awake_left() -> Option<Duration> {
+    let window = KEEP_AWAKE?;
+    let elapsed = LAST_INTERACTION.lock(|i| i.get())?.elapsed();
+    (elapsed < window).then(|| window - elapsed)
+}
+
+/// Backlight level forced from the serial console (`bright`), `None` when automatic.
+static BACKLIGHT_OVERRIDE: CriticalSectionMutex<Cell<Option<u8>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+/// Wakes `fade_screen` up to apply a new override or keep-awake window right away.
+static BACKLIGHT_OVERRIDE_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/** A task to prove that we can do other things that render_loops */
+#[embassy_executor::task]
+pub async fn fade_screen(bl: LedChannel, rtc: Rc<RTCUtils>) {
+    loop {
+        let bl_level = match (BACKLIGHT_MODE, controller::night_factor()) {
+            (BacklightMode::Twilight, Some(night_factor)) => {
+                // linear from full brightness in daylight to the night level when dark
+                let range = (BACKLIGHT_DAY - BACKLIGHT_NIGHT) as f32;
+                BACKLIGHT_DAY - (range * night_factor) as u8
+            }
+            // no sky computed yet, fall back on the clock
+            _ => {
+                let settings = settings::current();
+                let d = rtc.get_date_time().await.with_timezone(&settings.timezone);
+                let (day_start, day_end) = settings.backlight_day_hours;
+                let mut bl_level = BACKLIGHT_NIGHT;
+                if (d.hour() >= day_start as u32 && d.hour() < day_end as u32) {
+                    bl_level = BACKLIGHT_DAY;
+                } else if (d.hour() == day_end as u32) {
+                    // a 30% duty
+                    bl_level = 58;
+                }
+                bl_level
+            }
+        };
+        let keep_awake = keep_awake_left();
+        let bl_level = if keep_awake.is_some() { BACKLIGHT_DAY } else { bl_level };
+        let bl_level = BACKLIGHT_OVERRIDE.lock(|o| o.get()).unwrap_or(bl_level);
+        let bl_level = if controller::screen_is_on() { bl_level } else { 0 };
+        // the sunrise lights the backlight up even if the screen was turned off
+        let bl_level = match controller::sunrise_ramp() {
+            Some(ramp) => bl_level.max((BACKLIGHT_DAY as f32 * ramp) as u8),
+            None => bl_level,
+        };
+        bl.set_duty_hw(backlight_duty(bl_level));
+        log::trace!("Setting backlight to {}", bl_level);
+        select3(
+            controller::SCREEN_POWER_CHANGED.wait(),
+            BACKLIGHT_OVERRIDE_CHANGED.wait(),
+            match keep_awake {
+                // back to the schedule as soon as the window is over
+                Some(left) => Timer::after(left.min(Duration::from_secs(10))),
+                // smoother steps while the sunrise ramps up
+                None if controller::sunrise_ramp().is_some() => Timer::after_secs(1),
+                None => Timer::after_secs(10),
+            },
+        )
+        .await;
+        // Timer::after_millis(10).await;
+        // bl.set_duty(bl_level).unwrap();
+        // if increase {
+        //     bl_level = bl_level + 1;
+        // } else {
+        //     bl_level = bl_level - 1;
+        // }
+    }
+}
+
+/// Reads commands from the serial console, see `cli`.
+#[embassy_executor::task]
+pub async fn serial_cli(mut serial_rx: UsbSerialJtagRx<'static, Async>) {
+    let mut line = heapless::String::<{ cli::MAX_LINE_LEN }>::new();
+    let mut overflowed = false;
+    let mut buffer = [0u8; 16];
+    loop {
+        let Ok(len) = embedded_io_async::Read::read(&mut serial_rx, &mut buffer).await else {
+            continue;
+        };
+        for &byte in &buffer[..len] {
+            match byte {
+                b'\r' | b'\n' => {
+                    if overflowed {
+                        log::warn!("cli: line longer than {} bytes dropped", cli::MAX_LINE_LEN);
+                    } else if !line.is_empty() {
+                        run_command(&line);
+                    }
+                    line.clear();
+                    overflowed = false;
+                }
+                _ => overflowed |= line.push(byte as char).is_err(),
+            }
+        }
+    }
+}
+
+fn run_command(line: &str) {
+    log::info!("cli: {}", line);
+    match cli::parse(line) {
+        Ok(Command::Action(action)) => controller::send_action(action),
+        Ok(Command::Brightness(level)) => {
+            BACKLIGHT_OVERRIDE.lock(|o| o.set(level));
+            BACKLIGHT_OVERRIDE_CHANGED.signal(());
+        }
+        #[cfg(not(feature = "offline"))]
+        Ok(Command::Sync) => ntp::SYNC_REQUEST.signal(()),
+        #[cfg(feature = "offline")]
+        Ok(Command::Sync) => log::warn!("cli: no NTP in offline mode"),
+        Ok(Command::Help) => log::info!("{}", cli::HELP),
+        Err(e) => log::warn!("cli: {}", e),
+    }
+}
+
+#[embassy_executor::task]
+pub async fn temperature_task(rtc: Rc<RTCUtils>) {
+    loop {
+        let celsius = rtc.temperature_sensor.get_temperature().to_celsius();
+        controller::send_action(Action::TemperatureUpdate(celsius));
+        Timer::after(Duration::from_secs(5)).await;
+    }
+}
+
+/// Gathers the health of every subsystem for the status page, at a low rate.
+#[embassy_executor::task]
+pub async fn system_status_task(rtc: Rc<RTCUtils>, stack: Option<Stack<'static>>) {
+    let mut heap_trend = controller::HeapTrend::new();
+    loop {
+        let wifi = match stack {
+            Some(stack) if stack.is_config_up() => slint_generated::WifiState::OK,
+            Some(stack) if stack.is_link_up() => slint_generated::WifiState::LINKUP,
+            Some(_) => slint_generated::WifiState::STARTING,
+            None => slint_generated::WifiState::OK,
+        };
+        #[cfg(not(feature = "offline"))]
+        let ntp_synced_secs_ago = ntp::last_sync().map(|i| i.elapsed().as_secs());
+        #[cfg(feature = "offline")]
+        let ntp_synced_secs_ago = None;
+
+        let heap_free = esp_alloc::HEAP.free();
+        controller::send_action(Action::SystemStatus(controller::SystemStatus {
+            wifi,
+            ntp_synced_secs_ago,
+            rtc_trusted: rtc.is_trusted().await,
+            time_source: rtc.source().name(),
+            heap_free,
+            cpu_mhz: cpu_clock::cpu_mhz(),
+        }));
+        if let Some(warning) = heap_trend.push(heap_free) {
+            controller::send_action(warning);
+        }
+        Timer::after(Duration::from_secs(30)).await;
+    }
+}
+
+/// A WiFi drop shorter than this doesn't change the indicator.
+#[cfg(not(feature = "offline"))]
+const WIFI_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[cfg(not(feature = "offline"))]
+fn wifi_rank(state: slint_generated::WifiState) -> u8 {
+    match state {
+        slint_generated::WifiState::STARTING => 0,
+        slint_generated::WifiState::LINKUP => 1,
+        slint_generated::WifiState::OK => 2,
+    }
+}
+
+#[cfg(not(feature = "offline"))]
+#[embassy_executor::task]
+pub async fn wifi_status_task(stack: Stack<'static>) {
+    let mut shown = slint_generated::WifiState::STARTING;
+    let mut degraded_since: Option<Instant> = None;
+    // last address sent to the controller, sent again after a reconnection
+    let mut assigned_ip = None;
+    loop {
+        let observed = if (stack.is_link_up()) {
+            if (stack.is_config_up()) {
+                slint_generated::WifiState::OK
+            } else {
+                slint_generated::WifiState::LINKUP
+            }
+        } else {
+            slint_generated::WifiState::STARTING
+        };
+
+        // improvements show up right away, drops only once they last
+        if wifi_rank(observed) >= wifi_rank(shown)
+            || degraded_since.get_or_insert_with(Instant::now).elapsed() >= WIFI_GRACE_PERIOD
+        {
+            degraded_since = None;
+            shown = observed;
+        }
+        controller::send_action(Action::WifiStateUpdate(shown));
+        if shown != slint_generated::WifiState::OK {
+            assigned_ip = None;
+        } else if let Some(config) = stack.config_v4() {
+            let address = config.address.address();
+            if assigned_ip != Some(address) {
+                assigned_ip = Some(address);
+                controller::send_action(Action::IpAssigned(address));
+            }
+        }
+
+        // refresh_signal.signal(());
+        if (!stack.is_config_up()) {
+            Timer::after(Duration::from_millis(50)).await;
+        } else {
+            Timer::after(Duration::from_secs(10)).await;
+        }
+    }
+}
+#[cfg(not(feature = "offline"))]
+#[embassy_executor::task]
+pub async fn update_rtc_with_ntp(rtc: Rc<RTCUtils>) {
+    loop {
+        let now = await_now().await;
+        let drift = (rtc.get_date_time().await - now).num_seconds().abs();
+        if drift < RTC_MAX_DRIFT_SECS && rtc.is_trusted().await {
+            log::debug!("RTC within {}s of NTP, not rewritten", drift);
+        } else {
+            log::info!("Update time ! {} (RTC was {}s off)", now, drift);
+            rtc.set_date_time(now.to_utc()).await;
+        }
+        Timer::after(RTC_MIN_WRITE_INTERVAL).await;
+    }
+}
+
+#[cfg(not(feature = "offline"))]
+#[embassy_executor::task]
+pub async fn run_ntp_client(ntp_client: NtpClient<'static>) {
+    ntp_client.run().await;
+}
+
+#[embassy_executor::task]
+pub async fn update_timer(rtc: Rc<RTCUtils>) {
+    let mut visible = true;
+    let mut last_value = 0;
+    let mut ticker = Ticker::every(Duration::from_millis(1000));
+    #[cfg(feature = "rtc-32khz")]
+    let mut last_read: Option<(chrono::DateTime<chrono::Utc>, u64)> = None;
+    loop {
+        let timezone = settings::current().timezone;
+        #[cfg(not(feature = "rtc-32khz"))]
+        let current_time = rtc.get_date_time().await.with_timezone(&timezone);
+
+        // the clock was set since (NTP sync...), the time read before is stale
+        #[cfg(feature = "rtc-32khz")]
+        if crate::board::CLOCK_WRITTEN.try_take().is_some() {
+            last_read = None;
+        }
+        // only hit the I2C bus every RTC_RESYNC_SECS, the 32kHz timebase covers the gap
+        #[cfg(feature = "rtc-32khz")]
+        let current_time = match last_read {
+            Some((read_at, ticks))
+                if timebase::is_counting()
+                    && timebase::elapsed_since(ticks).as_secs() < RTC_RESYNC_SECS =>
+            {
+                let elapsed = timebase::elapsed_since(ticks).as_micros() as i64;
+                let time = read_at + chrono::TimeDelta::microseconds(elapsed);
+                time.with_timezone(&timezone)
+            }
+            _ => {
+                if !timebase::is_counting() {
+                    crate::log_throttled!(
+                        60_000,
+                        log::Level::Warn,
+                        "no 32kHz signal from the RTC, reading it every second"
+                    );
+                }
+                let ticks = timebase::ticks();
+                let read_at = rtc.get_date_time().await;
+                last_read = Some((read_at, ticks));
+                read_at.with_timezone(&timezone)
+            }
+        };
+
+        let scaled_time =
+            controller::scaled_time(current_time.to_utc()).with_timezone(&timezone);
+
+        let actual = current_time.second() / controller::monster_blink_period_secs();
+        if (actual != last_value) {
+            visible = !visible;
+        }
+        last_value = actual;
+
+        controller::send_action(Action::MultipleActions(vec![
+            Action::ShowMonster(visible),
+            Action::ShowColon(!controller::BLINK_COLON || current_time.second() % 2 == 0),
+            Action::UpdateTime(scaled_time),
+            // Action::TimeOfDayUpdate(tod, moon),
+        ]));
+
+        log::debug!(
+            "Setting visible monster: {} (actual: {}, last_value{}, current_time: {})",
+            visible,
+            actual,
+            last_value,
+            current_time
+        );
+        // trigger refresh
+        // refresh_signal.signal(());
+
+        // Double trigger
+
+        ticker.next().await;
+        // Timer::after_millis(10).await;
+    }
+}
@@ -0,0 +1,86 @@
+//! Holdover timebase disciplined by the DS3231 32.768kHz output.
+//!
+//! The square wave is counted by the PCNT peripheral, which is far more stable than the
+//! ESP's own clock, so `update_timer` can interpolate between (slow) RTC reads over I2C.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::{
+    gpio::Input,
+    pcnt::{channel::EdgeMode, unit::Unit},
+};
+
+/// Frequency of the DS3231 32kHz output.
+pub const RTC_32KHZ_FREQUENCY: u64 = 32_768;
+
+/// The PCNT counter wraps back to 0 when reaching this value.
+const COUNTER_HIGH_LIMIT: i16 = 30_000;
+
+/// How often the hardware counter is accumulated, must stay well below
+/// `COUNTER_HIGH_LIMIT / RTC_32KHZ_FREQUENCY` seconds.
+const POLL_INTERVAL_MS: u64 = 250;
+
+/// Longest time without a 32kHz period counted before the count is taken as stopped, a few
+/// `POLL_INTERVAL_MS`.
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+static TICKS: CriticalSectionMutex<Cell<u64>> = CriticalSectionMutex::new(Cell::new(0));
+/// When `poll` last saw the count move, see `is_counting`.
+static LAST_COUNTED: CriticalSectionMutex<Cell<Option<Instant>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+pub struct Ds3231Timebase {
+    unit: Unit<'static, 0>,
+    last: i16,
+}
+
+impl Ds3231Timebase {
+    pub fn new(unit: Unit<'static, 0>, input: Input<'static>) -> Self {
+        unit.set_high_limit(Some(COUNTER_HIGH_LIMIT)).unwrap();
+        unit.clear();
+        let channel = &unit.channel0;
+        channel.set_edge_signal(input.peripheral_input());
+        channel.set_input_mode(EdgeMode::Hold, EdgeMode::Increment);
+        unit.resume();
+        Self { unit, last: 0 }
+    }
+
+    pub async fn run(mut self) -> ! {
+        loop {
+            self.poll();
+            Timer::after(Duration::from_millis(POLL_INTERVAL_MS)).await;
+        }
+    }
+
+    fn poll(&mut self) {
+        let value = self.unit.value();
+        let delta = if value >= self.last {
+            value - self.last
+        } else {
+            COUNTER_HIGH_LIMIT - self.last + value
+        };
+        self.last = value;
+        TICKS.lock(|t| t.set(t.get() + delta as u64));
+        if delta > 0 {
+            LAST_COUNTED.lock(|l| l.set(Some(Instant::now())));
+        }
+    }
+}
+
+/// Monotonic count of 32kHz periods since the timebase started.
+pub fn ticks() -> u64 {
+    TICKS.lock(|t| t.get())
+}
+
+/// Time elapsed since `ticks` was sampled with [`ticks`].
+pub fn elapsed_since(ticks: u64) -> Duration {
+    Duration::from_micros((self::ticks() - ticks) * 1_000_000 / RTC_32KHZ_FREQUENCY)
+}
+
+/// False while the 32kHz signal is missing, e.g. the RTC output could not be enabled or the
+/// GPIO isn't wired: `elapsed_since` then stands still and the RTC has to be read instead.
+pub fn is_counting() -> bool {
+    LAST_COUNTED.lock(|l| l.get()).is_some_and(|at| at.elapsed() < STALL_TIMEOUT)
+}
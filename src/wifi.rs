@@ -1,6 +1,12 @@
-use embassy_futures::join;
+use embassy_futures::{
+    join,
+    select::{select, Either},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use embassy_time::{Duration, Timer};
-use esp_wifi::wifi::{ClientConfiguration, Configuration, WifiController, WifiEvent, WifiState};
+use esp_wifi::wifi::{
+    ClientConfiguration, Configuration, WifiController, WifiError, WifiEvent, WifiState,
+};
 
 // pub trait MyWifiController {
 //     async fn run();
@@ -9,39 +15,93 @@ use esp_wifi::wifi::{ClientConfiguration, Configuration, WifiController, WifiEve
 const SSID: &str = env!("SSID");
 const PASSWORD: &str = env!("PASSWORD");
 
+/// Access points kept by `scan`, the total count is still reported.
+const SCAN_MAX_RESULTS: usize = 8;
+
+/// New credentials for the connection task, see `request_reconfigure`.
+static RECONFIGURE: Signal<CriticalSectionRawMutex, ClientConfiguration> = Signal::new();
+
+/// Asks the connection task to join another network. It is applied once, between two
+/// connection attempts: several requests in a row only apply the last one.
+pub fn request_reconfigure(config: ClientConfiguration) {
+    RECONFIGURE.signal(config);
+}
+
 pub struct EspEmbassyWifiController<'a> {
     ctrl: WifiController<'a>,
+    config: ClientConfiguration,
 }
 
 impl<'a> EspEmbassyWifiController<'a> {
     pub fn new<'b>(ctrl: WifiController<'b>) -> EspEmbassyWifiController<'b> {
-        EspEmbassyWifiController::<'b> { ctrl }
+        EspEmbassyWifiController::<'b> {
+            ctrl,
+            config: ClientConfiguration {
+                ssid: SSID.try_into().unwrap(),
+                password: PASSWORD.try_into().unwrap(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Stops the WiFi and swaps the credentials, `connection` then starts and connects again
+    /// with them. Only called from `connection`, so it never races a connection attempt.
+    async fn reconfigure(&mut self, config: ClientConfiguration) {
+        log::info!("wifi: leaving {} for {}", self.config.ssid, config.ssid);
+        if matches!(self.ctrl.is_started(), Ok(true)) {
+            if let Err(e) = self.ctrl.stop_async().await {
+                log::error!("wifi: could not stop: {:?}", e);
+            }
+        }
+        self.config = config;
+    }
+
+    /// Starts the radio, counts the access points in range and stops it again, for the boot
+    /// self test. `connection` starts it again by itself.
+    pub async fn scan(&mut self) -> Result<usize, WifiError> {
+        if !matches!(self.ctrl.is_started(), Ok(true)) {
+            self.ctrl.set_configuration(&Configuration::Client(self.config.clone()))?;
+            self.ctrl.start_async().await?;
+        }
+        let result = self.ctrl.scan_n_async::<SCAN_MAX_RESULTS>().await;
+        self.ctrl.stop_async().await?;
+        result.map(|(_, count)| count)
     }
 
     pub async fn connection(&mut self) {
         log::info!("start connection task");
         log::info!("Device capabilities: {:?}", self.ctrl.capabilities());
         loop {
+            if let Some(config) = RECONFIGURE.try_take() {
+                self.reconfigure(config).await;
+            }
             match esp_wifi::wifi::wifi_state() {
                 WifiState::StaConnected => {
-                    // wait until we're no longer connected
-                    self.ctrl.wait_for_event(WifiEvent::StaDisconnected).await;
-                    Timer::after(Duration::from_millis(5000)).await
+                    // wait until we're no longer connected, or asked to switch networks
+                    match select(
+                        self.ctrl.wait_for_event(WifiEvent::StaDisconnected),
+                        RECONFIGURE.wait(),
+                    )
+                    .await
+                    {
+                        Either::First(_) => Timer::after(Duration::from_millis(5000)).await,
+                        Either::Second(config) => self.reconfigure(config).await,
+                    }
                 }
                 _ => {}
             }
             if !matches!(self.ctrl.is_started(), Ok(true)) {
-                let client_config = Configuration::Client(ClientConfiguration {
-                    ssid: SSID.try_into().unwrap(),
-                    password: PASSWORD.try_into().unwrap(),
-                    ..Default::default()
-                });
+                let client_config = Configuration::Client(self.config.clone());
                 self.ctrl.set_configuration(&client_config).unwrap();
                 log::info!("Starting wifi");
                 self.ctrl.start_async().await.unwrap();
                 log::info!("Wifi started!");
             }
-            log::info!("About to connect to {} with {}...", SSID, PASSWORD);
+            log::info!(
+                "About to connect to {} with {}...",
+                self.config.ssid,
+                self.config.password
+            );
 
             match self.ctrl.connect_async().await {
                 Ok(_) => log::info!("Wifi connected!"),